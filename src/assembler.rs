@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+
+use crate::{program::{Entrypoint, Program}, vm::{instructions::{find_instruction, Instruction, Operand}, uint::U256, utils::{make_relative_reference, Direction}}};
+
+// Синтаксис:
+//   ; комментарий
+//   label:                     метка, можно на одной строке с инструкцией
+//   IPUSH64 10                 мнемоника и операнды через пробел
+//   JMP label / RJMP label     адреса и относительные переходы по меткам
+//   BPUSH 0xABCD / BPUSH "abc" данные блоком
+//   .data 0x00ff               сырые байты в коде
+//   .internal label [opcode]   точка входа, с opcode - обработчик в таблице
+//   .external label [opcode]
+//   .view label [opcode]
+//   .const 0x... / .meta "..." секции констант и метаданных
+//
+// Данные для программы кладутся в .const и читаются через LCONST.
+// .data нужен только чтобы дизассемблер мог вывести любой код: верификатор разбирает весь код подряд,
+// поэтому программа с .data деплой не пройдёт, а tfsmasm соберёт её лишь с --no-verify
+#[derive(Debug)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl AssembleError {
+    fn new(line: usize, message: String) -> Self {
+        Self { line, message }
+    }
+}
+
+impl ToString for AssembleError {
+    fn to_string(&self) -> String {
+        format!("line {}: {}", self.line, self.message)
+    }
+}
+
+enum Item {
+    Instruction { line: usize, offset: usize, instruction: Instruction, arguments: Vec<String> },
+    Data(Vec<u8>),
+}
+
+struct EntrypointDirective {
+    line: usize,
+    kind: String,
+    target: String,
+    opcode: Option<u64>,
+}
+
+fn tokenize(line: &str, number: usize) -> Result<Vec<String>, AssembleError> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == ';' {
+            break;
+        } else if c == '"' {
+            let mut token = String::from('"');
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => token.push('\n'),
+                        Some('t') => token.push('\t'),
+                        Some('0') => token.push('\0'),
+                        Some(escaped) => token.push(escaped),
+                        None => return Err(AssembleError::new(number, "unterminated string".to_string())),
+                    },
+                    Some(c) => token.push(c),
+                    None => return Err(AssembleError::new(number, "unterminated string".to_string())),
+                }
+            }
+            tokens.push(token);
+        } else {
+            let mut token = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ';' {
+                    break;
+                }
+                token.push(c);
+                chars.next();
+            }
+            tokens.push(token);
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_label(token: &str) -> bool {
+    let mut chars = token.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.'),
+        _ => false,
+    }
+}
+
+pub fn parse_number(token: &str) -> Option<u64> {
+    if let Some(hex) = token.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()
+    } else if let Some(negative) = token.strip_prefix('-') {
+        Some(negative.parse::<i64>().ok()?.checked_neg()? as u64)
+    } else {
+        token.parse::<u64>().ok()
+    }
+}
+
+fn parse_wide(token: &str) -> Option<U256> {
+    if let Some(hex) = token.strip_prefix("0x") {
+        let hex = if hex.len() % 2 == 1 { format!("0{}", hex) } else { hex.to_string() };
+        U256::from_be_slice(&hex::decode(hex).ok()?)
+    } else {
+        U256::from_dec_str(token)
+    }
+}
+
+// Строки приходят из tokenize с открывающей кавычкой в начале
+fn parse_bytes(token: &str) -> Option<Vec<u8>> {
+    if let Some(string) = token.strip_prefix('"') {
+        Some(string.as_bytes().to_vec())
+    } else if let Some(hex) = token.strip_prefix("0x") {
+        hex::decode(hex).ok()
+    } else {
+        None
+    }
+}
+
+fn instruction_size(instruction: &Instruction, arguments: &[String], line: usize) -> Result<usize, AssembleError> {
+    if arguments.len() != instruction.operands.len() {
+        return Err(AssembleError::new(line, format!("{} expects {} operands, got {}", instruction.mnemonic, instruction.operands.len(), arguments.len())));
+    }
+    let mut size = 1;
+    for (operand, argument) in instruction.operands.iter().zip(arguments) {
+        size += operand.size();
+        if *operand == Operand::Block {
+            size += parse_bytes(argument).ok_or(AssembleError::new(line, format!("bad data literal {}", argument)))?.len();
+        }
+    }
+    Ok(size)
+}
+
+fn resolve(labels: &HashMap<String, usize>, argument: &str, line: usize) -> Result<u64, AssembleError> {
+    if let Some(offset) = labels.get(argument) {
+        return Ok(*offset as u64);
+    }
+    if let Some(number) = parse_number(argument) {
+        return Ok(number);
+    }
+    Err(AssembleError::new(line, format!("unknown label {}", argument)))
+}
+
+fn encode_operand(code: &mut Vec<u8>, labels: &HashMap<String, usize>, operand: Operand, argument: &str, next: usize, line: usize) -> Result<(), AssembleError> {
+    let bad = || AssembleError::new(line, format!("bad operand {}", argument));
+    match operand {
+        Operand::U8 => {
+            let value = u8::try_from(parse_number(argument).ok_or_else(bad)?).map_err(|_| bad())?;
+            code.push(value);
+        },
+        Operand::U16 => {
+            let value = u16::try_from(parse_number(argument).ok_or_else(bad)?).map_err(|_| bad())?;
+            code.extend(value.to_be_bytes());
+        },
+        Operand::U64 | Operand::Address => {
+            code.extend(resolve(labels, argument, line)?.to_be_bytes());
+        },
+        Operand::U256 => {
+            code.extend(parse_wide(argument).ok_or_else(bad)?.to_be_bytes());
+        },
+        Operand::Block => {
+            let bytes = parse_bytes(argument).ok_or_else(bad)?;
+            code.extend((bytes.len() as u64).to_be_bytes());
+            code.extend(bytes);
+        },
+        Operand::Relative => {
+            let target = match labels.get(argument) {
+                Some(offset) => *offset as i64,
+                None => next as i64 + argument.parse::<i64>().map_err(|_| AssembleError::new(line, format!("unknown label {}", argument)))?,
+            };
+            let distance = target - next as i64;
+            let direction = if distance < 0 { Direction::Backward } else { Direction::Forward };
+            let reference = u16::try_from(distance.unsigned_abs()).ok()
+                .and_then(|offset| make_relative_reference(direction, offset))
+                .ok_or(AssembleError::new(line, format!("relative jump to {} is too far", argument)))?;
+            code.extend(reference.to_be_bytes());
+        },
+    }
+    Ok(())
+}
+
+pub fn assemble(source: &str) -> Result<Program, AssembleError> {
+    let mut items = Vec::new();
+    let mut labels = HashMap::new();
+    let mut entrypoints = Vec::new();
+    let mut constants = Vec::new();
+    let mut metadata = Vec::new();
+    let mut offset = 0;
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut tokens = tokenize(text, line)?;
+        while let Some(label) = tokens.first().and_then(|x| x.strip_suffix(':')).map(|x| x.to_string()) {
+            if !is_label(&label) {
+                return Err(AssembleError::new(line, format!("bad label {}", label)));
+            }
+            if labels.insert(label.clone(), offset).is_some() {
+                return Err(AssembleError::new(line, format!("duplicate label {}", label)));
+            }
+            tokens.remove(0);
+        }
+        let Some(head) = tokens.first().cloned() else { continue };
+        let arguments = tokens[1..].to_vec();
+        match head.as_str() {
+            ".internal" | ".external" | ".view" => {
+                let opcode = match arguments.len() {
+                    1 => None,
+                    2 => Some(parse_number(&arguments[1]).ok_or(AssembleError::new(line, format!("bad opcode {}", arguments[1])))?),
+                    _ => return Err(AssembleError::new(line, format!("{} expects a label and an optional opcode", head))),
+                };
+                entrypoints.push(EntrypointDirective { line, kind: head, target: arguments[0].clone(), opcode });
+            },
+            ".const" | ".meta" | ".data" => {
+                let mut bytes = Vec::new();
+                for argument in &arguments {
+                    bytes.extend(parse_bytes(argument).ok_or(AssembleError::new(line, format!("bad data literal {}", argument)))?);
+                }
+                match head.as_str() {
+                    ".const" => constants.extend(bytes),
+                    ".meta" => metadata.extend(bytes),
+                    _ => {
+                        offset += bytes.len();
+                        items.push(Item::Data(bytes));
+                    },
+                }
+            },
+            mnemonic => {
+                let instruction = find_instruction(&mnemonic.to_uppercase())
+                    .ok_or(AssembleError::new(line, format!("unknown instruction {}", mnemonic)))?;
+                let size = instruction_size(&instruction, &arguments, line)?;
+                items.push(Item::Instruction { line, offset, instruction, arguments });
+                offset += size;
+            },
+        }
+    }
+
+    let mut code = Vec::new();
+    for item in items {
+        match item {
+            Item::Data(bytes) => code.extend(bytes),
+            Item::Instruction { line, offset, instruction, arguments } => {
+                let next = offset + instruction_size(&instruction, &arguments, line)?;
+                code.push(instruction.opcode);
+                for (operand, argument) in instruction.operands.iter().zip(&arguments) {
+                    encode_operand(&mut code, &labels, *operand, argument, next, line)?;
+                }
+            },
+        }
+    }
+
+    let mut internal = Entrypoint::none();
+    let mut external = Entrypoint::none();
+    let mut view = Entrypoint::none();
+    for directive in entrypoints {
+        let target = resolve(&labels, &directive.target, directive.line)? as usize;
+        let entrypoint = match directive.kind.as_str() {
+            ".internal" => &mut internal,
+            ".external" => &mut external,
+            _ => &mut view,
+        };
+        match directive.opcode {
+            Some(opcode) => entrypoint.add_handler(opcode, target),
+            None => entrypoint.set_fallback(target),
+        }
+    }
+    Ok(Program::new(code, internal, external, view, constants, metadata))
+}
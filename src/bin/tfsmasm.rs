@@ -0,0 +1,38 @@
+use std::{env, fs, process};
+
+use tfsmvm::{assembler::assemble, verifier::verify};
+
+// tfsmasm [--no-verify] <source> - печатает программу в hex, в том виде, в котором она уходит в Init.
+// --no-verify нужен для вывода tfsmdis с .data: такую программу можно собрать обратно, но не задеплоить
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    let no_verify = args.len() == 3 && args[1] == "--no-verify";
+    if no_verify {
+        args.remove(1);
+    }
+    if args.len() != 2 {
+        eprintln!("usage: tfsmasm [--no-verify] <source>");
+        process::exit(2);
+    }
+    let source = match fs::read_to_string(&args[1]) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("{}: {}", args[1], error);
+            process::exit(1);
+        },
+    };
+    let program = match assemble(&source) {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("{}: {}", args[1], error.to_string());
+            process::exit(1);
+        },
+    };
+    if !no_verify {
+        if let Err(error) = verify(&program) {
+            eprintln!("{}: {}", args[1], error.to_string());
+            process::exit(1);
+        }
+    }
+    println!("{}", hex::encode(program.to_bytes()));
+}
@@ -0,0 +1,46 @@
+use std::{env, fs, process};
+
+use tfsmvm::{assembler::assemble, compiler::compile_to_assembly, verifier::verify};
+
+// tfsmc [-S] <source> - компилирует контракт и печатает программу в hex, с -S печатает ассемблер
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let (assembly_only, path) = match args.as_slice() {
+        [_, flag, path] if flag == "-S" => (true, path),
+        [_, path] => (false, path),
+        _ => {
+            eprintln!("usage: tfsmc [-S] <source>");
+            process::exit(2);
+        },
+    };
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            process::exit(1);
+        },
+    };
+    let assembly = match compile_to_assembly(&source) {
+        Ok(assembly) => assembly,
+        Err(error) => {
+            eprintln!("{}: {}", path, error.to_string());
+            process::exit(1);
+        },
+    };
+    if assembly_only {
+        print!("{}", assembly);
+        return;
+    }
+    let program = match assemble(&assembly) {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("{}: assembler: {}", path, error.to_string());
+            process::exit(1);
+        },
+    };
+    if let Err(error) = verify(&program) {
+        eprintln!("{}: {}", path, error.to_string());
+        process::exit(1);
+    }
+    println!("{}", hex::encode(program.to_bytes()));
+}
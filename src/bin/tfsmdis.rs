@@ -0,0 +1,34 @@
+use std::{env, fs, path::Path, process};
+
+use tfsmvm::{disassembler::disassemble_block, vm::block::Block};
+
+// tfsmdis <hex | файл с hex> - печатает программу в синтаксисе tfsmasm
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        eprintln!("usage: tfsmdis <hex | file>");
+        process::exit(2);
+    }
+    let text = if Path::new(&args[1]).is_file() {
+        match fs::read_to_string(&args[1]) {
+            Ok(text) => text,
+            Err(error) => {
+                eprintln!("{}: {}", args[1], error);
+                process::exit(1);
+            },
+        }
+    } else {
+        args[1].clone()
+    };
+    let Some(program) = Block::from_string(text.trim().to_string()) else {
+        eprintln!("program is not a hex string");
+        process::exit(1);
+    };
+    match disassemble_block(program) {
+        Ok(text) => print!("{}", text),
+        Err(error) => {
+            eprintln!("{}", error.to_string());
+            process::exit(1);
+        },
+    }
+}
@@ -0,0 +1,675 @@
+use std::collections::HashMap;
+
+use lexer::tokenize;
+use parser::{Expr, ExprKind, Function, Handler, Module, Parser, Stmt, StmtKind, Type};
+
+use crate::{assembler::{assemble, parse_number}, program::Program, vm::uint::U256};
+
+pub mod lexer;
+pub mod parser;
+
+// Язык контрактов:
+//   fn add(a: u64, b: u64) -> u64 { return a + b; }
+//   on external 1 { let x: u64 = to_u64(msg.body); set_data(to_block(add(x, 1))); }
+//   on view -> block { return data(); }
+// Типы: u64, u256, bool, block. Функции вызываются через CALL/RET,
+// аргументы и локальные переменные лежат в кадре ENTER/LEAVE
+#[derive(Debug)]
+pub struct CompileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl CompileError {
+    fn new(line: usize, message: String) -> Self {
+        Self { line, message }
+    }
+}
+
+impl ToString for CompileError {
+    fn to_string(&self) -> String {
+        format!("line {}: {}", self.line, self.message)
+    }
+}
+
+struct Signature {
+    params: Vec<Type>,
+    returns: Option<Type>,
+}
+
+// Встроенные функции: типы аргументов, результат и инструкции
+fn get_builtin(name: &str) -> Option<(&'static [Type], Option<Type>, &'static [&'static str])> {
+    let builtin: (&'static [Type], Option<Type>, &'static [&'static str]) = match name {
+        "data" => (&[], Some(Type::Block), &["LDATA"]),
+        "set_data" => (&[Type::Block], None, &["SDATA"]),
+        "load" => (&[Type::Block], Some(Type::Block), &["SLOAD"]),
+        "store" => (&[Type::Block, Type::Block], None, &["SSTORE"]),
+        "remove" => (&[Type::Block], None, &["SDEL"]),
+        "has" => (&[Type::Block], Some(Type::Bool), &["SHAS"]),
+        "send" => (&[Type::Block, Type::Block, Type::U64, Type::Block], None, &["SEND"]),
+        "constants" => (&[], Some(Type::Block), &["LCONST"]),
+        "hash" => (&[Type::Block], Some(Type::Block), &["BHASH"]),
+        "len" => (&[Type::Block], Some(Type::U64), &["BLEN", "NIP"]),
+        "concat" => (&[Type::Block, Type::Block], Some(Type::Block), &["BCAT"]),
+        "sub" => (&[Type::Block, Type::U64, Type::U64], Some(Type::Block), &["BSUB"]),
+        "to_u64" => (&[Type::Block], Some(Type::U64), &["BTON"]),
+        "to_u256" => (&[Type::Block], Some(Type::U256), &["BTOW"]),
+        "to_block" => (&[Type::U64], Some(Type::Block), &["NTOB"]),
+        "wide_to_block" => (&[Type::U256], Some(Type::Block), &["WTOB"]),
+        "widen" => (&[Type::U64], Some(Type::U256), &["WIDEN"]),
+        "narrow" => (&[Type::U256], Some(Type::U64), &["NARROW"]),
+        _ => return None,
+    };
+    Some(builtin)
+}
+
+fn get_message_field(name: &str) -> Option<(&'static str, Type)> {
+    match name {
+        "sender" => Some(("MSENDER", Type::Block)),
+        "receiver" => Some(("MSELF", Type::Block)),
+        "opcode" => Some(("MOPCODE", Type::U64)),
+        "body" => Some(("MBODY", Type::Block)),
+        "time" => Some(("MTIME", Type::U64)),
+        "type" => Some(("MTYPE", Type::U64)),
+        "hash" => Some(("MHASH", Type::Block)),
+        _ => None,
+    }
+}
+
+fn is_numeric(value_type: Type) -> bool {
+    matches!(value_type, Type::U64 | Type::U256)
+}
+
+// Все let функции, каждому достаётся своя ячейка в кадре
+fn count_locals(statements: &[Stmt]) -> usize {
+    statements.iter().map(|statement| match &statement.kind {
+        StmtKind::Let(..) => 1,
+        StmtKind::If(_, then, otherwise) => count_locals(then) + count_locals(otherwise),
+        StmtKind::While(_, body) => count_locals(body),
+        _ => 0,
+    }).sum()
+}
+
+// Ветка завершается return или throw на всех путях
+fn always_returns(statements: &[Stmt]) -> bool {
+    match statements.last().map(|statement| &statement.kind) {
+        Some(StmtKind::Return(_)) | Some(StmtKind::Throw(_)) => true,
+        Some(StmtKind::If(_, then, otherwise)) => always_returns(then) && always_returns(otherwise),
+        _ => false,
+    }
+}
+
+struct Codegen {
+    lines: Vec<String>,
+    // Строка исходника для каждой строки lines, чтобы ошибки ассемблера указывали в исходник
+    sources: Vec<usize>,
+    line: usize,
+    labels: usize,
+    signatures: HashMap<String, Signature>,
+    // Видимые переменные текущей функции: имя, тип и номер ячейки в кадре
+    variables: Vec<(String, Type, usize)>,
+    // Число занятых ячеек и размер кадра
+    slots: usize,
+    frame: usize,
+    returns: Option<Type>,
+    handler: bool,
+}
+
+impl Codegen {
+    fn new(signatures: HashMap<String, Signature>) -> Self {
+        Self {
+            lines: Vec::new(),
+            sources: Vec::new(),
+            line: 0,
+            labels: 0,
+            signatures,
+            variables: Vec::new(),
+            slots: 0,
+            frame: 0,
+            returns: None,
+            handler: false,
+        }
+    }
+
+    fn emit(&mut self, instruction: String) {
+        self.lines.push(format!("    {}", instruction));
+        self.sources.push(self.line);
+    }
+
+    fn label(&mut self, label: &str) {
+        self.lines.push(format!("{}:", label));
+        self.sources.push(self.line);
+    }
+
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!("_L{}", self.labels)
+    }
+
+    fn find_variable(&self, name: &str, line: usize) -> Result<(usize, Type), CompileError> {
+        self.variables.iter().rev().find(|(variable, _, _)| variable == name)
+            .map(|(_, value_type, slot)| (*slot, *value_type))
+            .ok_or(CompileError::new(line, format!("unknown variable {}", name)))
+    }
+
+    fn coerce(&mut self, actual: Type, expected: Type, line: usize) -> Result<(), CompileError> {
+        match (actual, expected) {
+            _ if actual == expected => Ok(()),
+            (Type::U64, Type::U256) => {
+                self.emit("WIDEN".to_string());
+                Ok(())
+            },
+            _ => Err(CompileError::new(line, format!("expected {}, found {}", expected.to_string(), actual.to_string()))),
+        }
+    }
+
+    fn compile_function(&mut self, function: &Function) -> Result<(), CompileError> {
+        self.line = function.line;
+        self.label(&format!("fn_{}", function.name));
+        self.returns = function.returns;
+        self.handler = false;
+        if function.returns.is_some() && !always_returns(&function.body) {
+            return Err(CompileError::new(function.line, format!("function {} may end without returning a value", function.name)));
+        }
+        let params = function.params.iter().enumerate().map(|(slot, (name, value_type))| (name.clone(), *value_type, slot)).collect();
+        self.enter(params, count_locals(&function.body));
+        self.compile_statements(&function.body)?;
+        if !always_returns(&function.body) {
+            self.leave();
+        }
+        Ok(())
+    }
+
+    fn compile_handler(&mut self, handler: &Handler, label: &str) -> Result<(), CompileError> {
+        self.line = handler.line;
+        self.label(label);
+        self.returns = handler.returns;
+        self.handler = true;
+        if handler.returns.is_some() && !always_returns(&handler.body) {
+            return Err(CompileError::new(handler.line, "handler may end without returning a value".to_string()));
+        }
+        self.enter(Vec::new(), count_locals(&handler.body));
+        self.compile_statements(&handler.body)?;
+        if !always_returns(&handler.body) {
+            self.leave();
+        }
+        Ok(())
+    }
+
+    // Аргументы уже на стеке, под переменные кладутся нули, и всё вместе становится кадром
+    fn enter(&mut self, params: Vec<(String, Type, usize)>, locals: usize) {
+        self.slots = params.len();
+        self.frame = params.len() + locals;
+        self.variables = params;
+        for _ in 0..locals {
+            self.emit("IPUSH8 0".to_string());
+        }
+        if self.frame > 0 {
+            self.emit(format!("ENTER {}", self.frame));
+        }
+    }
+
+    // Убирает кадр со стека, результат над ним остаётся, и выходит из функции или обработчика
+    fn leave(&mut self) {
+        if self.frame > 0 {
+            self.emit("LEAVE".to_string());
+        }
+        self.emit(if self.handler { "HALT" } else { "RET" }.to_string());
+    }
+
+    fn compile_scope(&mut self, statements: &[Stmt]) -> Result<(), CompileError> {
+        let (variables, line) = (self.variables.len(), self.line);
+        self.compile_statements(statements)?;
+        self.variables.truncate(variables);
+        self.line = line;
+        Ok(())
+    }
+
+    fn compile_statements(&mut self, statements: &[Stmt]) -> Result<(), CompileError> {
+        for statement in statements {
+            self.compile_statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn compile_statement(&mut self, statement: &Stmt) -> Result<(), CompileError> {
+        let line = statement.line;
+        self.line = line;
+        match &statement.kind {
+            StmtKind::Let(name, value_type, value) => {
+                let actual = self.compile_value(value)?;
+                self.coerce(actual, *value_type, line)?;
+                self.emit(format!("LSTORE {}", self.slots));
+                self.variables.push((name.clone(), *value_type, self.slots));
+                self.slots += 1;
+            },
+            StmtKind::Assign(name, value) => {
+                let (slot, value_type) = self.find_variable(name, line)?;
+                let actual = self.compile_value(value)?;
+                self.coerce(actual, value_type, line)?;
+                self.emit(format!("LSTORE {}", slot));
+            },
+            StmtKind::If(condition, then, otherwise) => {
+                let otherwise_label = self.new_label();
+                let end_label = self.new_label();
+                self.compile_condition(condition)?;
+                self.emit(format!("JMF {}", otherwise_label));
+                self.compile_scope(then)?;
+                if !otherwise.is_empty() {
+                    self.emit(format!("JMP {}", end_label));
+                }
+                self.label(&otherwise_label);
+                if !otherwise.is_empty() {
+                    self.compile_scope(otherwise)?;
+                    self.label(&end_label);
+                }
+            },
+            StmtKind::While(condition, body) => {
+                let start_label = self.new_label();
+                let end_label = self.new_label();
+                self.label(&start_label);
+                self.compile_condition(condition)?;
+                self.emit(format!("JMF {}", end_label));
+                self.compile_scope(body)?;
+                self.emit(format!("JMP {}", start_label));
+                self.label(&end_label);
+            },
+            StmtKind::Return(value) => {
+                match (value, self.returns) {
+                    (Some(value), Some(expected)) => {
+                        let actual = self.compile_value(value)?;
+                        self.coerce(actual, expected, line)?;
+                        self.leave();
+                    },
+                    (None, None) => self.leave(),
+                    (Some(_), None) => return Err(CompileError::new(line, "unexpected return value".to_string())),
+                    (None, Some(expected)) => return Err(CompileError::new(line, format!("expected return value of type {}", expected.to_string()))),
+                }
+            },
+            StmtKind::Throw(code) => {
+                self.emit(format!("THROW {}", code));
+            },
+            StmtKind::Require(condition, code) => {
+                self.compile_condition(condition)?;
+                self.emit(format!("THROWIFNOT {}", code));
+            },
+            StmtKind::Expr(value) => {
+                if self.compile_expr(value)?.is_some() {
+                    self.emit("DROPN 1".to_string());
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn compile_condition(&mut self, condition: &Expr) -> Result<(), CompileError> {
+        let actual = self.compile_value(condition)?;
+        self.coerce(actual, Type::Bool, condition.line)
+    }
+
+    fn compile_value(&mut self, expr: &Expr) -> Result<Type, CompileError> {
+        self.compile_expr(expr)?.ok_or(CompileError::new(expr.line, "expression has no value".to_string()))
+    }
+
+    fn compile_number(&mut self, number: &str, line: usize) -> Result<Type, CompileError> {
+        if let Some(value) = parse_number(number) {
+            if value <= u8::MAX as u64 {
+                self.emit(format!("IPUSH8 {}", value));
+            } else {
+                self.emit(format!("IPUSH64 {}", value));
+            }
+            return Ok(Type::U64);
+        }
+        let wide = match number.strip_prefix("0x") {
+            Some(hex) => hex::decode(if hex.len() % 2 == 1 { format!("0{}", hex) } else { hex.to_string() }).ok()
+                .and_then(|bytes| U256::from_be_slice(&bytes)),
+            None => U256::from_dec_str(number),
+        };
+        let wide = wide.ok_or(CompileError::new(line, format!("bad number {}", number)))?;
+        self.emit(format!("IPUSH256 {}", wide.to_string()));
+        Ok(Type::U256)
+    }
+
+    fn compile_expr(&mut self, expr: &Expr) -> Result<Option<Type>, CompileError> {
+        let line = expr.line;
+        let value_type = match &expr.kind {
+            ExprKind::Number(number) => self.compile_number(number, line)?,
+            ExprKind::Bytes(bytes) => {
+                self.emit(format!("BPUSH 0x{}", hex::encode(bytes)));
+                Type::Block
+            },
+            ExprKind::Bool(value) => {
+                self.emit(format!("IPUSH8 {}", *value as u8));
+                Type::Bool
+            },
+            ExprKind::Var(name) => {
+                let (slot, value_type) = self.find_variable(name, line)?;
+                self.emit(format!("LLOAD {}", slot));
+                value_type
+            },
+            ExprKind::Message(field) => {
+                let (instruction, value_type) = get_message_field(field)
+                    .ok_or(CompileError::new(line, format!("unknown message field {}", field)))?;
+                self.emit(instruction.to_string());
+                value_type
+            },
+            ExprKind::Unary(operator, operand) => {
+                let operand_type = self.compile_value(operand)?;
+                match (*operator, operand_type) {
+                    ("!", Type::Bool) => self.emit("LNOT".to_string()),
+                    ("~", Type::U64 | Type::U256) => self.emit("NOT".to_string()),
+                    ("-", Type::U64) => self.emit("NEG".to_string()),
+                    _ => return Err(CompileError::new(line, format!("operator {} is not defined for {}", operator, operand_type.to_string()))),
+                }
+                operand_type
+            },
+            ExprKind::Binary(operator @ ("&&" | "||"), left, right) => {
+                let end_label = self.new_label();
+                self.compile_condition(left)?;
+                self.emit("DUP".to_string());
+                self.emit(format!("{} {}", if *operator == "&&" { "JMF" } else { "JMT" }, end_label));
+                self.emit("DROPN 1".to_string());
+                self.compile_condition(right)?;
+                self.label(&end_label);
+                Type::Bool
+            },
+            ExprKind::Binary(operator, left, right) => {
+                let left_type = self.compile_value(left)?;
+                let right_type = self.compile_value(right)?;
+                self.compile_binary(operator, left_type, right_type, line)?
+            },
+            ExprKind::Call(name, arguments) => return self.compile_call(name, arguments, line),
+        };
+        Ok(Some(value_type))
+    }
+
+    fn compile_binary(&mut self, operator: &str, left: Type, right: Type, line: usize) -> Result<Type, CompileError> {
+        let numeric = is_numeric(left) && is_numeric(right);
+        let wide = if left == Type::U256 || right == Type::U256 { Type::U256 } else { Type::U64 };
+        let (instruction, value_type) = match operator {
+            "+" if left == Type::Block && right == Type::Block => ("BCAT", Type::Block),
+            "+" if numeric => ("ADD", wide),
+            "-" if numeric => ("SUB", wide),
+            "*" if numeric => ("MUL", wide),
+            "/" if numeric => ("DIV", wide),
+            "%" if numeric => ("MOD", wide),
+            "&" if numeric => ("AND", wide),
+            "|" if numeric => ("OR", wide),
+            "^" if numeric => ("XOR", wide),
+            "&" if left == Type::Bool && right == Type::Bool => ("AND", Type::Bool),
+            "|" if left == Type::Bool && right == Type::Bool => ("OR", Type::Bool),
+            "^" if left == Type::Bool && right == Type::Bool => ("XOR", Type::Bool),
+            "<<" if is_numeric(left) && right == Type::U64 => ("SHL", left),
+            ">>" if is_numeric(left) && right == Type::U64 => ("SHR", left),
+            "<" if numeric => ("CML", Type::Bool),
+            ">" if numeric => ("CMB", Type::Bool),
+            "<=" if numeric => ("CMLE", Type::Bool),
+            ">=" if numeric => ("CMBE", Type::Bool),
+            "==" if numeric || (left == Type::Bool && right == Type::Bool) => ("CME", Type::Bool),
+            "!=" if numeric || (left == Type::Bool && right == Type::Bool) => ("CMNE", Type::Bool),
+            "==" if left == Type::Block && right == Type::Block => ("BEQ", Type::Bool),
+            "!=" if left == Type::Block && right == Type::Block => {
+                self.emit("BEQ".to_string());
+                ("LNOT", Type::Bool)
+            },
+            _ => return Err(CompileError::new(line, format!("operator {} is not defined for {} and {}", operator, left.to_string(), right.to_string()))),
+        };
+        self.emit(instruction.to_string());
+        Ok(value_type)
+    }
+
+    fn compile_arguments(&mut self, name: &str, params: &[Type], arguments: &[Expr], line: usize) -> Result<(), CompileError> {
+        if params.len() != arguments.len() {
+            return Err(CompileError::new(line, format!("{} expects {} arguments, got {}", name, params.len(), arguments.len())));
+        }
+        for (param, argument) in params.iter().zip(arguments) {
+            let actual = self.compile_value(argument)?;
+            self.coerce(actual, *param, argument.line)?;
+        }
+        Ok(())
+    }
+
+    fn compile_call(&mut self, name: &str, arguments: &[Expr], line: usize) -> Result<Option<Type>, CompileError> {
+        if let Some((params, returns, instructions)) = get_builtin(name) {
+            self.compile_arguments(name, params, arguments, line)?;
+            for instruction in instructions {
+                self.emit(instruction.to_string());
+            }
+            return Ok(returns);
+        }
+        let signature = self.signatures.get(name).ok_or(CompileError::new(line, format!("unknown function {}", name)))?;
+        let (params, returns) = (signature.params.clone(), signature.returns);
+        self.compile_arguments(name, &params, arguments, line)?;
+        self.emit(format!("CALL fn_{}", name));
+        Ok(returns)
+    }
+}
+
+fn handler_label(handler: &Handler) -> String {
+    match handler.opcode {
+        Some(opcode) => format!("on_{}_{}", handler.kind, opcode),
+        None => format!("on_{}", handler.kind),
+    }
+}
+
+// Строки программы для ассемблера: точки входа, обработчики, затем функции. Рядом - строки исходника для них
+fn generate(source: &str) -> Result<(Vec<String>, Vec<usize>), CompileError> {
+    let Module { functions, handlers } = Parser::new(tokenize(source)?).parse_module()?;
+
+    let mut signatures = HashMap::new();
+    for function in &functions {
+        if get_builtin(&function.name).is_some() {
+            return Err(CompileError::new(function.line, format!("{} is a built-in function", function.name)));
+        }
+        let signature = Signature { params: function.params.iter().map(|(_, value_type)| *value_type).collect(), returns: function.returns };
+        if signatures.insert(function.name.clone(), signature).is_some() {
+            return Err(CompileError::new(function.line, format!("duplicate function {}", function.name)));
+        }
+    }
+
+    let mut codegen = Codegen::new(signatures);
+    let mut header = Vec::new();
+    let mut sources = Vec::new();
+    let mut seen = Vec::new();
+    for handler in &handlers {
+        if seen.contains(&(&handler.kind, handler.opcode)) {
+            return Err(CompileError::new(handler.line, format!("duplicate {} handler", handler.kind)));
+        }
+        seen.push((&handler.kind, handler.opcode));
+        let label = handler_label(handler);
+        header.push(match handler.opcode {
+            Some(opcode) => format!(".{} {} {}", handler.kind, label, opcode),
+            None => format!(".{} {}", handler.kind, label),
+        });
+        sources.push(handler.line);
+        codegen.compile_handler(handler, &label)?;
+    }
+    for function in &functions {
+        codegen.compile_function(function)?;
+    }
+    header.push(String::new());
+    sources.push(0);
+    header.extend(codegen.lines);
+    sources.extend(codegen.sources);
+    Ok((header, sources))
+}
+
+pub fn compile_to_assembly(source: &str) -> Result<String, CompileError> {
+    let (lines, _) = generate(source)?;
+    Ok(lines.join("\n") + "\n")
+}
+
+pub fn compile(source: &str) -> Result<Program, CompileError> {
+    let (lines, sources) = generate(source)?;
+    assemble(&(lines.join("\n") + "\n")).map_err(|error| {
+        let line = error.line.checked_sub(1).and_then(|index| sources.get(index)).copied().unwrap_or(0);
+        CompileError::new(line, format!("assembler: {}", error.message))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::{block::Block, message::{Message, MessageType}, trap::{ExitCode, Trap}, LoadStorage, SendMessage, Value, VM};
+
+    use super::{compile, generate};
+
+    struct Host;
+
+    impl SendMessage for Host {
+        fn send_message(&mut self, _message: Message) -> Result<(), Trap> {
+            Ok(())
+        }
+
+        fn sent_messages(&self) -> usize {
+            0
+        }
+
+        fn cancel_messages(&mut self, _count: usize) {}
+    }
+
+    impl LoadStorage for Host {
+        fn load_storage(&self, _key: Block) -> Option<Block> {
+            None
+        }
+    }
+
+    // Запускает внешний обработчик opcode, возвращает код выхода и вершину стека
+    fn run(source: &str, opcode: u64) -> (ExitCode, Option<Value>) {
+        let program = compile(source).map_err(|error| error.to_string()).unwrap();
+        let entrypoint = program.get_entrypoint(MessageType::External, opcode).unwrap();
+        let message = Message::new(MessageType::External, Block::empty(), opcode, Block::empty(), Block::empty(), None, 1_000_000);
+        let mut host = Host;
+        let mut vm = VM::new(program.get_code(), entrypoint, Block::from(program.get_constants()), Block::empty(), message, 1_000_000, &mut host);
+        let exit_code = vm.run();
+        (exit_code, vm.stack().first().cloned())
+    }
+
+    fn run_number(source: &str, opcode: u64) -> u64 {
+        match run(source, opcode) {
+            (ExitCode::Success, Some(Value::Number(number))) => number,
+            (exit_code, _) => panic!("handler {} failed: {}", opcode, exit_code.to_string()),
+        }
+    }
+
+    fn compile_error(source: &str) -> String {
+        match compile(source) {
+            Ok(_) => panic!("compiled: {}", source),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn recursion() {
+        let source = "
+            fn fact(n: u64) -> u64 {
+                if n == 0 {
+                    return 1;
+                }
+                return n * fact(n - 1);
+            }
+            fn fib(n: u64) -> u64 {
+                if n < 2 {
+                    return n;
+                }
+                return fib(n - 1) + fib(n - 2);
+            }
+            on external 1 -> u64 { return fact(5); }
+            on external 2 -> u64 { return fib(15); }
+        ";
+        assert_eq!(run_number(source, 1), 120);
+        assert_eq!(run_number(source, 2), 610);
+    }
+
+    #[test]
+    fn while_with_early_return() {
+        let source = "
+            fn isqrt(n: u64) -> u64 {
+                let i: u64 = 0;
+                while true {
+                    let square: u64 = (i + 1) * (i + 1);
+                    if square > n {
+                        return i;
+                    }
+                    i = i + 1;
+                }
+                return 0;
+            }
+            on external 1 -> u64 { return isqrt(50); }
+            on external 2 -> u64 {
+                let total: u64 = 0;
+                let i: u64 = 0;
+                while i < 100 {
+                    total = total + i;
+                    if total > 20 {
+                        return total;
+                    }
+                    i = i + 1;
+                }
+                return 0;
+            }
+        ";
+        assert_eq!(run_number(source, 1), 7);
+        assert_eq!(run_number(source, 2), 21);
+    }
+
+    #[test]
+    fn short_circuit() {
+        let source = "
+            fn fail() -> bool { throw 9; }
+            on external 1 -> bool { return false && fail(); }
+            on external 2 -> bool { return true || fail(); }
+            on external 3 -> bool { return true && fail(); }
+            on external 4 -> bool { return false || fail(); }
+            on external 5 -> bool { return 1 < 2 && 3 > 2 && !(1 == 2); }
+        ";
+        assert_eq!(run_number(source, 1), 0);
+        assert_eq!(run_number(source, 2), 1);
+        assert_eq!(run(source, 3).0, ExitCode::Trap(Trap::Throw(9)));
+        assert_eq!(run(source, 4).0, ExitCode::Trap(Trap::Throw(9)));
+        assert_eq!(run_number(source, 5), 1);
+    }
+
+    #[test]
+    fn u64_to_u256_coercion() {
+        let source = "
+            fn square(a: u256) -> u256 { return a * a; }
+            on external 1 -> u256 { return square(1 << 40); }
+            on external 2 -> u256 {
+                let x: u256 = 5;
+                x = x + 18446744073709551615;
+                return x;
+            }
+            on external 3 -> u256 { return 7; }
+        ";
+        let wide = |opcode| match run(source, opcode) {
+            (ExitCode::Success, Some(Value::Wide(number))) => number.to_string(),
+            (exit_code, _) => panic!("handler {} failed: {}", opcode, exit_code.to_string()),
+        };
+        assert_eq!(wide(1), "1208925819614629174706176");
+        assert_eq!(wide(2), "18446744073709551620");
+        assert_eq!(wide(3), "7");
+    }
+
+    #[test]
+    fn type_errors() {
+        assert_eq!(compile_error("fn f(a: u64) -> u64 {\n    let b: block = \"x\";\n    return a + b;\n}"), "line 3: operator + is not defined for u64 and block");
+        assert_eq!(compile_error("on external {\n    let x: u64 = \"a\";\n}"), "line 2: expected u64, found block");
+        assert_eq!(compile_error("fn f(a: u256) -> u64 {\n    return a;\n}"), "line 2: expected u64, found u256");
+        assert_eq!(compile_error("on external {\n    if 1 { }\n}"), "line 2: expected bool, found u64");
+        assert_eq!(compile_error("fn f(a: u64) { }\non external {\n    f(\"x\");\n}"), "line 3: expected u64, found block");
+        assert_eq!(compile_error("on view -> u64 {\n    if true { return 1; }\n}"), "line 1: handler may end without returning a value");
+        assert_eq!(compile_error("on external {\n    let x: u64 = y;\n}"), "line 2: unknown variable y");
+    }
+
+    // Ошибки ассемблера переводятся в строки исходника через эту таблицу
+    #[test]
+    fn assembly_lines_map_to_source() {
+        let source = "on external 1 {\n    let x: u64 = 5;\n    while x > 0 {\n        x = x - 1;\n    }\n    throw 7;\n}\n";
+        let (lines, sources) = generate(source).map_err(|error| error.to_string()).unwrap();
+        assert_eq!(lines.len(), sources.len());
+        let line_of = |instruction: &str| lines.iter().position(|line| line.trim() == instruction).map(|index| sources[index]);
+        assert_eq!(line_of(".external on_external_1 1"), Some(1));
+        assert_eq!(line_of("SUB"), Some(4));
+        assert_eq!(line_of("THROW 7"), Some(6));
+    }
+}
@@ -0,0 +1,99 @@
+use super::CompileError;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Token {
+    Ident(String),
+    Number(String),
+    Bytes(Vec<u8>),
+    Symbol(&'static str),
+    End,
+}
+
+// Длинные символы идут раньше, чтобы "<=" не разобрался как "<" и "="
+const SYMBOLS: &[&str] = &[
+    "->", "==", "!=", "<=", ">=", "<<", ">>", "&&", "||",
+    "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "|", "^",
+    "(", ")", "{", "}", ",", ";", ":", ".",
+];
+
+#[derive(Clone, Debug)]
+pub struct Lexeme {
+    pub token: Token,
+    pub line: usize,
+}
+
+fn read_string(chars: &[char], position: &mut usize, line: usize) -> Result<String, CompileError> {
+    let mut value = String::new();
+    *position += 1;
+    loop {
+        match chars.get(*position) {
+            Some('"') => break,
+            Some('\\') => {
+                *position += 1;
+                match chars.get(*position) {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('0') => value.push('\0'),
+                    Some(escaped) => value.push(*escaped),
+                    None => return Err(CompileError::new(line, "unterminated string".to_string())),
+                }
+            },
+            Some('\n') | None => return Err(CompileError::new(line, "unterminated string".to_string())),
+            Some(c) => value.push(*c),
+        }
+        *position += 1;
+    }
+    *position += 1;
+    Ok(value)
+}
+
+pub fn tokenize(source: &str) -> Result<Vec<Lexeme>, CompileError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut lexemes = Vec::new();
+    let mut position = 0;
+    let mut line = 1;
+    while position < chars.len() {
+        let c = chars[position];
+        if c == '\n' {
+            line += 1;
+            position += 1;
+        } else if c.is_whitespace() {
+            position += 1;
+        } else if c == '/' && chars.get(position + 1) == Some(&'/') {
+            while position < chars.len() && chars[position] != '\n' {
+                position += 1;
+            }
+        } else if c == '"' {
+            let value = read_string(&chars, &mut position, line)?;
+            lexemes.push(Lexeme { token: Token::Bytes(value.into_bytes()), line });
+        } else if c.is_ascii_digit() {
+            let start = position;
+            while position < chars.len() && chars[position].is_ascii_alphanumeric() {
+                position += 1;
+            }
+            lexemes.push(Lexeme { token: Token::Number(chars[start..position].iter().collect()), line });
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = position;
+            while position < chars.len() && (chars[position].is_ascii_alphanumeric() || chars[position] == '_') {
+                position += 1;
+            }
+            let ident: String = chars[start..position].iter().collect();
+            // hex"abcd" - блок, записанный в hex
+            if ident == "hex" && chars.get(position) == Some(&'"') {
+                let value = read_string(&chars, &mut position, line)?;
+                let bytes = hex::decode(&value).map_err(|_| CompileError::new(line, format!("bad hex literal {}", value)))?;
+                lexemes.push(Lexeme { token: Token::Bytes(bytes), line });
+            } else {
+                lexemes.push(Lexeme { token: Token::Ident(ident), line });
+            }
+        } else {
+            let rest: String = chars[position..chars.len().min(position + 2)].iter().collect();
+            let symbol = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol))
+                .ok_or(CompileError::new(line, format!("unexpected character {}", c)))?;
+            position += symbol.len();
+            lexemes.push(Lexeme { token: Token::Symbol(symbol), line });
+        }
+    }
+    lexemes.push(Lexeme { token: Token::End, line });
+    Ok(lexemes)
+}
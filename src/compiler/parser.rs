@@ -0,0 +1,369 @@
+use super::{lexer::{Lexeme, Token}, CompileError};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Type {
+    U64,
+    U256,
+    Bool,
+    Block,
+}
+
+impl ToString for Type {
+    fn to_string(&self) -> String {
+        match self {
+            Type::U64 => "u64",
+            Type::U256 => "u256",
+            Type::Bool => "bool",
+            Type::Block => "block",
+        }.to_string()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ExprKind {
+    Number(String),
+    Bytes(Vec<u8>),
+    Bool(bool),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Message(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub line: usize,
+}
+
+#[derive(Clone, Debug)]
+pub enum StmtKind {
+    Let(String, Type, Expr),
+    Assign(String, Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Throw(u16),
+    Require(Expr, u16),
+    Expr(Expr),
+}
+
+#[derive(Clone, Debug)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub line: usize,
+}
+
+pub struct Function {
+    pub name: String,
+    pub params: Vec<(String, Type)>,
+    pub returns: Option<Type>,
+    pub body: Vec<Stmt>,
+    pub line: usize,
+}
+
+// on internal|external|view [opcode] [-> type] { ... }
+pub struct Handler {
+    pub kind: String,
+    pub opcode: Option<u64>,
+    pub returns: Option<Type>,
+    pub body: Vec<Stmt>,
+    pub line: usize,
+}
+
+pub struct Module {
+    pub functions: Vec<Function>,
+    pub handlers: Vec<Handler>,
+}
+
+// Уровни приоритета бинарных операторов, от слабого к сильному
+const BINARY: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+pub struct Parser {
+    lexemes: Vec<Lexeme>,
+    position: usize,
+}
+
+impl Parser {
+    pub fn new(lexemes: Vec<Lexeme>) -> Self {
+        Self { lexemes, position: 0 }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.lexemes[self.position].token
+    }
+
+    fn line(&self) -> usize {
+        self.lexemes[self.position].line
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.lexemes[self.position].token.clone();
+        if token != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, CompileError> {
+        Err(CompileError::new(self.line(), message))
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Token::Symbol(s) if *s == symbol)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Ident(ident) if ident == keyword)
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), CompileError> {
+        if !self.is_symbol(symbol) {
+            return self.error(format!("expected {}", symbol));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), CompileError> {
+        if !self.is_keyword(keyword) {
+            return self.error(format!("expected {}", keyword));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_ident(&mut self) -> Result<String, CompileError> {
+        match self.advance() {
+            Token::Ident(ident) => Ok(ident),
+            _ => self.error("expected identifier".to_string()),
+        }
+    }
+
+    fn expect_number<T: TryFrom<u64>>(&mut self) -> Result<T, CompileError> {
+        let line = self.line();
+        match self.advance() {
+            Token::Number(number) => crate::assembler::parse_number(&number)
+                .and_then(|number| T::try_from(number).ok())
+                .ok_or(CompileError::new(line, format!("bad number {}", number))),
+            _ => Err(CompileError::new(line, "expected number".to_string())),
+        }
+    }
+
+    fn parse_type(&mut self) -> Result<Type, CompileError> {
+        match self.expect_ident()?.as_str() {
+            "u64" => Ok(Type::U64),
+            "u256" => Ok(Type::U256),
+            "bool" => Ok(Type::Bool),
+            "block" => Ok(Type::Block),
+            other => self.error(format!("unknown type {}", other)),
+        }
+    }
+
+    fn parse_returns(&mut self) -> Result<Option<Type>, CompileError> {
+        if self.is_symbol("->") {
+            self.advance();
+            Ok(Some(self.parse_type()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn parse_module(&mut self) -> Result<Module, CompileError> {
+        let mut module = Module { functions: Vec::new(), handlers: Vec::new() };
+        while *self.peek() != Token::End {
+            let line = self.line();
+            if self.is_keyword("fn") {
+                self.advance();
+                let name = self.expect_ident()?;
+                self.expect_symbol("(")?;
+                let mut params = Vec::new();
+                while !self.is_symbol(")") {
+                    let name = self.expect_ident()?;
+                    self.expect_symbol(":")?;
+                    params.push((name, self.parse_type()?));
+                    if !self.is_symbol(")") {
+                        self.expect_symbol(",")?;
+                    }
+                }
+                self.advance();
+                let returns = self.parse_returns()?;
+                let body = self.parse_block()?;
+                module.functions.push(Function { name, params, returns, body, line });
+            } else if self.is_keyword("on") {
+                self.advance();
+                let kind = self.expect_ident()?;
+                if !matches!(kind.as_str(), "internal" | "external" | "view") {
+                    return self.error(format!("unknown message type {}", kind));
+                }
+                let opcode = match self.peek() {
+                    Token::Number(_) => Some(self.expect_number()?),
+                    _ => None,
+                };
+                let returns = self.parse_returns()?;
+                let body = self.parse_block()?;
+                module.handlers.push(Handler { kind, opcode, returns, body, line });
+            } else {
+                return self.error("expected fn or on".to_string());
+            }
+        }
+        Ok(module)
+    }
+
+    fn parse_block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect_symbol("{")?;
+        let mut statements = Vec::new();
+        while !self.is_symbol("}") {
+            if *self.peek() == Token::End {
+                return self.error("unexpected end of file".to_string());
+            }
+            statements.push(self.parse_statement()?);
+        }
+        self.advance();
+        Ok(statements)
+    }
+
+    fn parse_if(&mut self) -> Result<Stmt, CompileError> {
+        let line = self.line();
+        self.expect_keyword("if")?;
+        let condition = self.parse_expr()?;
+        let then = self.parse_block()?;
+        let otherwise = if self.is_keyword("else") {
+            self.advance();
+            if self.is_keyword("if") {
+                vec![self.parse_if()?]
+            } else {
+                self.parse_block()?
+            }
+        } else {
+            Vec::new()
+        };
+        Ok(Stmt { kind: StmtKind::If(condition, then, otherwise), line })
+    }
+
+    fn parse_statement(&mut self) -> Result<Stmt, CompileError> {
+        let line = self.line();
+        if self.is_keyword("if") {
+            return self.parse_if();
+        }
+        let kind = if self.is_keyword("let") {
+            self.advance();
+            let name = self.expect_ident()?;
+            self.expect_symbol(":")?;
+            let value_type = self.parse_type()?;
+            self.expect_symbol("=")?;
+            StmtKind::Let(name, value_type, self.parse_expr()?)
+        } else if self.is_keyword("while") {
+            self.advance();
+            let condition = self.parse_expr()?;
+            let body = self.parse_block()?;
+            return Ok(Stmt { kind: StmtKind::While(condition, body), line });
+        } else if self.is_keyword("return") {
+            self.advance();
+            if self.is_symbol(";") {
+                StmtKind::Return(None)
+            } else {
+                StmtKind::Return(Some(self.parse_expr()?))
+            }
+        } else if self.is_keyword("throw") {
+            self.advance();
+            StmtKind::Throw(self.expect_number()?)
+        } else if self.is_keyword("require") {
+            self.advance();
+            self.expect_symbol("(")?;
+            let condition = self.parse_expr()?;
+            self.expect_symbol(",")?;
+            let code = self.expect_number()?;
+            self.expect_symbol(")")?;
+            StmtKind::Require(condition, code)
+        } else if matches!(self.peek(), Token::Ident(_)) && matches!(self.lexemes[self.position + 1].token, Token::Symbol("=")) {
+            let name = self.expect_ident()?;
+            self.advance();
+            StmtKind::Assign(name, self.parse_expr()?)
+        } else {
+            StmtKind::Expr(self.parse_expr()?)
+        };
+        self.expect_symbol(";")?;
+        Ok(Stmt { kind, line })
+    }
+
+    pub fn parse_expr(&mut self) -> Result<Expr, CompileError> {
+        self.parse_binary(0)
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        if level == BINARY.len() {
+            return self.parse_unary();
+        }
+        let mut left = self.parse_binary(level + 1)?;
+        while let Token::Symbol(symbol) = *self.peek() {
+            if !BINARY[level].contains(&symbol) {
+                break;
+            }
+            let line = self.line();
+            self.advance();
+            let right = self.parse_binary(level + 1)?;
+            left = Expr { kind: ExprKind::Binary(symbol, Box::new(left), Box::new(right)), line };
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, CompileError> {
+        let line = self.line();
+        if let Token::Symbol(symbol @ ("!" | "~" | "-")) = *self.peek() {
+            self.advance();
+            let operand = self.parse_unary()?;
+            return Ok(Expr { kind: ExprKind::Unary(symbol, Box::new(operand)), line });
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, CompileError> {
+        let line = self.line();
+        let kind = match self.advance() {
+            Token::Number(number) => ExprKind::Number(number),
+            Token::Bytes(bytes) => ExprKind::Bytes(bytes),
+            Token::Symbol("(") => {
+                let expr = self.parse_expr()?;
+                self.expect_symbol(")")?;
+                return Ok(expr);
+            },
+            Token::Ident(ident) if ident == "true" => ExprKind::Bool(true),
+            Token::Ident(ident) if ident == "false" => ExprKind::Bool(false),
+            Token::Ident(ident) if ident == "msg" => {
+                self.expect_symbol(".")?;
+                ExprKind::Message(self.expect_ident()?)
+            },
+            Token::Ident(ident) => {
+                if self.is_symbol("(") {
+                    self.advance();
+                    let mut arguments = Vec::new();
+                    while !self.is_symbol(")") {
+                        arguments.push(self.parse_expr()?);
+                        if !self.is_symbol(")") {
+                            self.expect_symbol(",")?;
+                        }
+                    }
+                    self.advance();
+                    ExprKind::Call(ident, arguments)
+                } else {
+                    ExprKind::Var(ident)
+                }
+            },
+            _ => return Err(CompileError::new(line, "expected expression".to_string())),
+        };
+        Ok(Expr { kind, line })
+    }
+}
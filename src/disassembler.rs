@@ -0,0 +1,210 @@
+use std::collections::BTreeSet;
+
+use crate::{program::{Program, ProgramError, ProgramReaderFromBytes}, vm::{block::Block, decoder::{decode, Decoded, Immediate}, utils::Direction}};
+
+enum Line {
+    Instruction(Decoded),
+    // Байты, которые не разбираются как инструкция, выводятся через .data
+    Data(usize, u8),
+}
+
+fn label(offset: usize) -> String {
+    format!("L_{:04x}", offset)
+}
+
+fn decode_lines(code: &[u8]) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        match decode(code, offset) {
+            Ok(decoded) => {
+                offset = decoded.next;
+                lines.push(Line::Instruction(decoded));
+            },
+            Err(_) => {
+                lines.push(Line::Data(offset, code[offset]));
+                offset += 1;
+            },
+        }
+    }
+    lines
+}
+
+// Метки ставятся только на начала инструкций и конец кода, остальные адреса выводятся числами
+fn collect_labels(program: &Program, lines: &[Line], length: usize) -> BTreeSet<usize> {
+    let boundaries: BTreeSet<usize> = lines.iter().filter_map(|line| match line {
+        Line::Instruction(decoded) => Some(decoded.offset),
+        Line::Data(..) => None,
+    }).chain([length]).collect();
+    let mut targets: Vec<usize> = [program.get_internal(), program.get_external(), program.get_view()].iter()
+        .flat_map(|entrypoint| entrypoint.get_offsets())
+        .collect();
+    for line in lines {
+        if let Line::Instruction(decoded) = line {
+            targets.extend(decoded.targets().into_iter().flatten());
+        }
+    }
+    targets.into_iter().filter(|target| boundaries.contains(target)).collect()
+}
+
+fn format_immediate(decoded: &Decoded, immediate: &Immediate, labels: &BTreeSet<usize>) -> String {
+    match immediate {
+        Immediate::U8(value) => value.to_string(),
+        Immediate::U16(value) => value.to_string(),
+        Immediate::U64(value) => value.to_string(),
+        Immediate::U256(value) => value.to_string(),
+        Immediate::Block(block) => format!("0x{}", hex::encode(block.bytes())),
+        Immediate::Address(target) if labels.contains(target) => label(*target),
+        Immediate::Address(target) => target.to_string(),
+        Immediate::Relative(direction, offset) => {
+            let target = match direction {
+                Direction::Forward => decoded.next.checked_add(*offset as usize),
+                Direction::Backward => decoded.next.checked_sub(*offset as usize),
+            };
+            match (target, direction) {
+                (Some(target), _) if labels.contains(&target) => label(target),
+                (_, Direction::Forward) => format!("+{}", offset),
+                (_, Direction::Backward) => format!("-{}", offset),
+            }
+        },
+    }
+}
+
+fn format_entrypoint(kind: &str, offset: usize, opcode: Option<u64>, labels: &BTreeSet<usize>) -> String {
+    let target = if labels.contains(&offset) { label(offset) } else { offset.to_string() };
+    match opcode {
+        Some(opcode) => format!("{} {} {}", kind, target, opcode),
+        None => format!("{} {}", kind, target),
+    }
+}
+
+// Текст в синтаксисе ассемблера: собирается обратно в тот же код.
+// Смещение каждой инструкции и абсолютный адрес относительных переходов выводятся в комментариях
+pub fn disassemble(program: &Program) -> String {
+    let code = program.get_code();
+    let lines = decode_lines(&code);
+    let labels = collect_labels(program, &lines, code.len());
+    let mut output = Vec::new();
+
+    for (kind, entrypoint) in [(".internal", program.get_internal()), (".external", program.get_external()), (".view", program.get_view())] {
+        if let Some(offset) = entrypoint.get_fallback() {
+            output.push(format_entrypoint(kind, offset, None, &labels));
+        }
+        for (opcode, offset) in entrypoint.get_handlers() {
+            output.push(format_entrypoint(kind, offset, Some(opcode), &labels));
+        }
+    }
+    let constants = program.get_constants();
+    if !constants.is_empty() {
+        output.push(format!(".const 0x{}", hex::encode(constants)));
+    }
+    let metadata = program.get_metadata();
+    if !metadata.is_empty() {
+        output.push(format!(".meta 0x{}", hex::encode(metadata)));
+    }
+    output.push(String::new());
+
+    for line in &lines {
+        let (offset, text) = match line {
+            Line::Instruction(decoded) => {
+                let mut text = decoded.instruction.mnemonic.to_string();
+                for immediate in &decoded.immediates {
+                    text.push(' ');
+                    text.push_str(&format_immediate(decoded, immediate, &labels));
+                }
+                let mut comment = format!("{:04x}", decoded.offset);
+                for target in decoded.targets().into_iter().flatten() {
+                    comment.push_str(&format!(" -> {:04x}", target));
+                }
+                (decoded.offset, format!("    {:<40} ; {}", text, comment))
+            },
+            Line::Data(offset, byte) => (*offset, format!("    {:<40} ; {:04x}", format!(".data 0x{:02x}", byte), offset)),
+        };
+        if labels.contains(&offset) {
+            output.push(format!("{}:", label(offset)));
+        }
+        output.push(text);
+    }
+    if labels.contains(&code.len()) {
+        output.push(format!("{}:", label(code.len())));
+    }
+    output.join("\n") + "\n"
+}
+
+pub fn disassemble_block(program: Block) -> Result<String, ProgramError> {
+    let program = ProgramReaderFromBytes::new(&program.unpack()).load()?;
+    Ok(disassemble(&program))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{assembler::assemble, program::Program, verifier::verify};
+
+    use super::disassemble;
+
+    fn assert_round_trip(program: &Program) {
+        let text = disassemble(program);
+        let reassembled = assemble(&text).map_err(|error| error.to_string()).unwrap();
+        assert_eq!(reassembled.to_bytes(), program.to_bytes(), "{}", text);
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = r#"
+            .internal start
+            .external start
+            .external other 7
+            .external end 9
+            .view other
+            .const 0x0102
+            .meta "meta"
+            start:
+                IPUSH256 115792089237316195423570985008687907853269984665640564039457584007913129639935
+                IPUSH256 0x01
+                IPUSH8 1
+                RJMT other
+                JMP 3
+                .data 0xff
+            other:
+                RJMP start
+                BPUSH "abc"
+                TRY end
+                ENDTRY
+                RJMF end
+                HALT
+                .data 0x00
+            end:
+        "#;
+        assert_round_trip(&assemble(source).map_err(|error| error.to_string()).unwrap());
+    }
+
+    // Код, прошедший верификацию, разбирается целиком, и вывод собирается обратно без .data
+    #[test]
+    fn verified_round_trip() {
+        let source = r#"
+            .external start
+            .const "data"
+            start:
+                LCONST
+                BLEN
+                HALT
+        "#;
+        let program = assemble(source).map_err(|error| error.to_string()).unwrap();
+        verify(&program).map_err(|error| error.to_string()).unwrap();
+        let text = disassemble(&program);
+        assert!(!text.contains(".data"), "{}", text);
+        verify(&assemble(&text).map_err(|error| error.to_string()).unwrap()).map_err(|error| error.to_string()).unwrap();
+    }
+
+    #[test]
+    fn data_is_rejected_by_verifier() {
+        let program = assemble(".external start\nstart:\n    HALT\n    .data 0xff\n").map_err(|error| error.to_string()).unwrap();
+        assert!(verify(&program).is_err());
+    }
+
+    #[test]
+    fn round_trip_without_entrypoints() {
+        assert_round_trip(&assemble(".data 0x0001ff\n").map_err(|error| error.to_string()).unwrap());
+        assert_round_trip(&assemble("").map_err(|error| error.to_string()).unwrap());
+    }
+}
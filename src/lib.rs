@@ -0,0 +1,8 @@
+pub mod vm;
+pub mod program;
+pub mod repositories;
+pub mod server;
+pub mod verifier;
+pub mod assembler;
+pub mod disassembler;
+pub mod compiler;
@@ -1,9 +1,4 @@
-use server::Server;
-
-mod vm;
-mod program;
-mod repositories;
-mod server;
+use tfsmvm::server::Server;
 
 fn main() {
     let server = Server::new();
@@ -1,12 +1,117 @@
-use crate::vm::message::MessageType;
+use crate::vm::{block::Block, builder::Builder, message::MessageType};
+
+// Точка входа для одного типа сообщений: обработчики по opcode сообщения и запасной вариант
+#[derive(Clone)]
+pub struct Entrypoint {
+    fallback: Option<usize>,
+    handlers: Vec<(u64, usize)>,
+}
+
+impl Entrypoint {
+    pub fn none() -> Self {
+        Self {
+            fallback: None,
+            handlers: Vec::new(),
+        }
+    }
+
+    pub fn set_fallback(&mut self, offset: usize) {
+        self.fallback = Some(offset);
+    }
+
+    pub fn add_handler(&mut self, opcode: u64, offset: usize) {
+        self.handlers.push((opcode, offset));
+    }
+
+    pub fn get_fallback(&self) -> Option<usize> {
+        self.fallback
+    }
+
+    pub fn get_handlers(&self) -> Vec<(u64, usize)> {
+        self.handlers.clone()
+    }
+
+    pub fn get_offsets(&self) -> Vec<usize> {
+        self.fallback.iter().copied().chain(self.handlers.iter().map(|(_, offset)| *offset)).collect()
+    }
+
+    pub fn resolve(&self, opcode: u64) -> Option<usize> {
+        match self.handlers.iter().find(|(handler, _)| *handler == opcode) {
+            Some((_, offset)) => Some(*offset),
+            None => self.fallback,
+        }
+    }
+
+    // Запасная точка входа пишется так же, как запись вида 0/1
+    fn write_fallback(&self, builder: &mut Builder) {
+        match self.fallback {
+            Some(offset) => {
+                builder.write_u8(1);
+                builder.write_u64(offset as u64);
+            },
+            None => builder.write_u8(0),
+        }
+    }
+
+    fn write(&self, builder: &mut Builder) {
+        if self.handlers.is_empty() {
+            self.write_fallback(builder);
+            return;
+        }
+        builder.write_u8(2);
+        self.write_fallback(builder);
+        builder.write_u64(self.handlers.len() as u64);
+        for (opcode, offset) in &self.handlers {
+            builder.write_u64(*opcode);
+            builder.write_u64(*offset as u64);
+        }
+    }
+}
+
+pub const MAGIC: &[u8; 4] = b"TFSM";
+pub const VERSION: u8 = 1;
+
+pub const SECTION_ENTRYPOINTS: u8 = 0;
+pub const SECTION_CODE: u8 = 1;
+pub const SECTION_CONSTANTS: u8 = 2;
+pub const SECTION_METADATA: u8 = 3;
+
+#[derive(Debug)]
+pub enum ProgramError {
+    Truncated,
+    UnsupportedVersion(u8),
+    UnknownSection(u8),
+    DuplicateSection(u8),
+    MissingCode,
+    TrailingBytes(usize),
+    BadEntrypointKind(u8),
+}
+
+impl ToString for ProgramError {
+    fn to_string(&self) -> String {
+        match self {
+            ProgramError::Truncated => "program is truncated".to_string(),
+            ProgramError::UnsupportedVersion(version) => format!("unsupported program version {}", version),
+            ProgramError::UnknownSection(kind) => format!("unknown section {}", kind),
+            ProgramError::DuplicateSection(kind) => format!("duplicate section {}", kind),
+            ProgramError::MissingCode => "program has no code section".to_string(),
+            ProgramError::TrailingBytes(length) => format!("{} trailing bytes after section", length),
+            ProgramError::BadEntrypointKind(kind) => format!("bad entrypoint kind {}", kind),
+        }
+    }
+}
 
 pub struct Program {
     code: Vec<u8>,
-    internal: Option<usize>,
-    external: Option<usize>,
-    view: Option<usize>,
+    internal: Entrypoint,
+    external: Entrypoint,
+    view: Entrypoint,
+    constants: Vec<u8>,
+    metadata: Vec<u8>,
 }
 
+// Контейнер: MAGIC, версия, число секций и секции [вид, длина u64, байты].
+// Старый формат без MAGIC (три точки входа и сразу код) тоже читается
 pub struct ProgramReaderFromBytes<'a> {
     offset: usize,
     bytes: &'a [u8],
@@ -20,60 +125,193 @@ impl<'a> ProgramReaderFromBytes<'a> {
         }
     }
 
-    pub fn read_u8(&mut self) -> Option<u8> {
-        let value = self.bytes.get(self.offset)?.clone();
+    pub fn read_u8(&mut self) -> Result<u8, ProgramError> {
+        let value = *self.bytes.get(self.offset).ok_or(ProgramError::Truncated)?;
         self.offset += 1; 
-        Some(value)
+        Ok(value)
     }
 
-    pub fn read_u64(&mut self) -> Option<u64> {
-        let value = self.bytes.get(self.offset..(self.offset + size_of::<u64>()))?;
-        self.offset += value.len(); 
-        Some(u64::from_be_bytes(value.try_into().ok()?))
+    pub fn read_u64(&mut self) -> Result<u64, ProgramError> {
+        let value = self.read_bytes(size_of::<u64>())?;
+        Ok(u64::from_be_bytes(value.try_into().unwrap()))
     }
 
-    pub fn load(&mut self) -> Option<Program> {
-        let mut internal = None;
-        if self.read_u8()? == 1 {
-            internal = self.read_u64().map(|x| x as usize);
+    pub fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], ProgramError> {
+        let end = self.offset.checked_add(length).ok_or(ProgramError::Truncated)?;
+        let value = self.bytes.get(self.offset..end).ok_or(ProgramError::Truncated)?;
+        self.offset = end;
+        Ok(value)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.offset
+    }
+
+    // В контейнере: 0 - нет точки входа, 1 - одно смещение, 2 - таблица [opcode, смещение] с запасной точкой входа
+    fn read_entrypoint(&mut self) -> Result<Entrypoint, ProgramError> {
+        let mut entrypoint = Entrypoint::none();
+        match self.read_u8()? {
+            0 => {},
+            1 => {
+                entrypoint.fallback = Some(self.read_u64()? as usize);
+            },
+            2 => {
+                if self.read_u8()? == 1 {
+                    entrypoint.fallback = Some(self.read_u64()? as usize);
+                }
+                let count = self.read_u64()?;
+                for _ in 0..count {
+                    let opcode = self.read_u64()?;
+                    let offset = self.read_u64()? as usize;
+                    entrypoint.handlers.push((opcode, offset));
+                }
+            },
+            kind => return Err(ProgramError::BadEntrypointKind(kind)),
         }
-        let mut external = None;
+        Ok(entrypoint)
+    }
+
+    fn read_entrypoints(&mut self) -> Result<(Entrypoint, Entrypoint, Entrypoint), ProgramError> {
+        let internal = self.read_entrypoint()?;
+        let external = self.read_entrypoint()?;
+        let view = self.read_entrypoint()?;
+        Ok((internal, external, view))
+    }
+
+    // В старом формате флаг 1 - смещение, любой другой байт - нет точки входа, таблиц там не бывает
+    fn read_legacy_entrypoint(&mut self) -> Result<Entrypoint, ProgramError> {
+        let mut entrypoint = Entrypoint::none();
         if self.read_u8()? == 1 {
-            external = self.read_u64().map(|x| x as usize);
+            entrypoint.fallback = Some(self.read_u64()? as usize);
         }
-        let mut view = None;
-        if self.read_u8()? == 1 {
-            view = self.read_u64().map(|x| x as usize);
+        Ok(entrypoint)
+    }
+
+    fn load_legacy(&mut self) -> Result<Program, ProgramError> {
+        let internal = self.read_legacy_entrypoint()?;
+        let external = self.read_legacy_entrypoint()?;
+        let view = self.read_legacy_entrypoint()?;
+        let code = Vec::from(self.read_bytes(self.remaining())?);
+        Ok(Program { code, internal, external, view, constants: Vec::new(), metadata: Vec::new() })
+    }
+
+    fn load_container(&mut self) -> Result<Program, ProgramError> {
+        self.read_bytes(MAGIC.len())?;
+        let version = self.read_u8()?;
+        if version != VERSION {
+            return Err(ProgramError::UnsupportedVersion(version));
+        }
+        let mut entrypoints = None;
+        let mut code = None;
+        let mut constants = None;
+        let mut metadata = None;
+        let count = self.read_u8()?;
+        for _ in 0..count {
+            let kind = self.read_u8()?;
+            let length = self.read_u64()?;
+            let bytes = self.read_bytes(length as usize)?;
+            let section = match kind {
+                SECTION_ENTRYPOINTS => {
+                    let mut reader = ProgramReaderFromBytes::new(bytes);
+                    let value = reader.read_entrypoints()?;
+                    if reader.remaining() > 0 {
+                        return Err(ProgramError::TrailingBytes(reader.remaining()));
+                    }
+                    entrypoints.replace(value).map(|_| ())
+                },
+                SECTION_CODE => code.replace(Vec::from(bytes)).map(|_| ()),
+                SECTION_CONSTANTS => constants.replace(Vec::from(bytes)).map(|_| ()),
+                SECTION_METADATA => metadata.replace(Vec::from(bytes)).map(|_| ()),
+                _ => return Err(ProgramError::UnknownSection(kind)),
+            };
+            if section.is_some() {
+                return Err(ProgramError::DuplicateSection(kind));
+            }
+        }
+        if self.remaining() > 0 {
+            return Err(ProgramError::TrailingBytes(self.remaining()));
+        }
+        let (internal, external, view) = entrypoints.unwrap_or((Entrypoint::none(), Entrypoint::none(), Entrypoint::none()));
+        Ok(Program {
+            code: code.ok_or(ProgramError::MissingCode)?,
+            internal,
+            external,
+            view,
+            constants: constants.unwrap_or_default(),
+            metadata: metadata.unwrap_or_default(),
+        })
+    }
+
+    pub fn load(&mut self) -> Result<Program, ProgramError> {
+        if self.bytes.starts_with(MAGIC) {
+            self.load_container()
+        } else {
+            self.load_legacy()
         }
-        let code = Vec::from(self.bytes.get(self.offset..self.bytes.len())?);
-        Some(
-            Program { code: code, internal: internal, external: external, view: view }
-        )
     }
 }
 
 impl Program {
+    pub fn new(code: Vec<u8>, internal: Entrypoint, external: Entrypoint, view: Entrypoint, constants: Vec<u8>, metadata: Vec<u8>) -> Self {
+        Self { code, internal, external, view, constants, metadata }
+    }
+
+    // Запись в формате контейнера текущей версии
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut entrypoints = Builder::new();
+        self.internal.write(&mut entrypoints);
+        self.external.write(&mut entrypoints);
+        self.view.write(&mut entrypoints);
+        let mut sections = vec![
+            (SECTION_ENTRYPOINTS, entrypoints.build()),
+            (SECTION_CODE, Block::new(&self.code)),
+        ];
+        if !self.constants.is_empty() {
+            sections.push((SECTION_CONSTANTS, Block::new(&self.constants)));
+        }
+        if !self.metadata.is_empty() {
+            sections.push((SECTION_METADATA, Block::new(&self.metadata)));
+        }
+        let mut builder = Builder::new();
+        builder.write_block(Block::new(MAGIC));
+        builder.write_u8(VERSION);
+        builder.write_u8(sections.len() as u8);
+        for (kind, section) in sections {
+            builder.write_u8(kind);
+            builder.write_block_with_len(section);
+        }
+        builder.build().unpack()
+    }
+
     pub fn get_code(&self) -> Vec<u8> {
         self.code.clone()
     }
 
-    pub fn get_internal(&self) -> Option<usize> {
-        self.internal.clone()
+    pub fn get_constants(&self) -> Vec<u8> {
+        self.constants.clone()
+    }
+
+    pub fn get_metadata(&self) -> Vec<u8> {
+        self.metadata.clone()
+    }
+
+    pub fn get_internal(&self) -> &Entrypoint {
+        &self.internal
     }
 
-    pub fn get_external(&self) -> Option<usize> {
-        self.external.clone()
+    pub fn get_external(&self) -> &Entrypoint {
+        &self.external
     }
 
-    pub fn get_view(&self) -> Option<usize> {
-        self.view.clone()
+    pub fn get_view(&self) -> &Entrypoint {
+        &self.view
     }
 
-    pub fn get_entrypoint(&self, message_type: MessageType) -> Option<usize> {
+    pub fn get_entrypoint(&self, message_type: MessageType, opcode: u64) -> Option<usize> {
         match message_type {
-            MessageType::Internal => self.get_internal(),
-            MessageType::External => self.get_external(),
-            MessageType::View => self.get_view(),
+            MessageType::Internal => self.get_internal().resolve(opcode),
+            MessageType::External => self.get_external().resolve(opcode),
+            MessageType::View => self.get_view().resolve(opcode),
         }
     }
 }
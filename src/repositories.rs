@@ -1,4 +1,4 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, rc::Rc, time::{SystemTime, UNIX_EPOCH}};
 
 use polodb_core::{bson::doc, CollectionT, Database};
 use serde::{Deserialize, Serialize};
@@ -23,6 +23,24 @@ struct SerdeContractState {
     pub timestamp: u64,
 }
 
+// Одна запись хранилища контракта, при изменении старая запись удаляется
+#[derive(Clone, Serialize, Deserialize)]
+struct SerdeStorageEntry {
+    pub address: String,
+    pub key: String,
+    pub value: String,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SerdeFailedState {
+    pub address: String,
+    pub message: String,
+    pub exit_code: String,
+    pub gas_used: u64,
+    pub timestamp: u64,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 struct SerdeInit {
     pub program: String,
@@ -39,6 +57,8 @@ struct SerdeMessage {
     pub opcode: u64,
     pub body: String,
     pub timestamp: u64,
+    #[serde(default)]
+    pub gas_limit: u64,
 }
 
 impl SerdeMessage {
@@ -78,6 +98,7 @@ impl SerdeMessage {
                 opcode: self.opcode,
                 body: Block::from_string(self.body.clone())?,
                 timestamp: self.timestamp,
+                gas_limit: self.gas_limit,
             }
         )
     }
@@ -92,6 +113,7 @@ impl SerdeMessage {
             opcode: message.opcode,
             body: message.body.to_string(),
             timestamp: message.timestamp,
+            gas_limit: message.gas_limit,
         }
     }
 }
@@ -114,22 +136,35 @@ impl Repository for PolaDBRef {
                 let message = SerdeMessage::from_message(&message);
                 self.poladb.collection::<SerdeMessage>("messages").insert_one(&message).unwrap();
             },
-            TransactionPart::State(contract_state) => {
-                let message = contract_state.message.clone();
-                if let Some(init) = contract_state.message.init {
-                    let contract = SerdeContract {
-                        address: contract_state.message.receiver.to_string(),
-                        program: init.program.to_string(),
-                        timestamp: contract_state.message.timestamp,
-                    };
-                    self.poladb.collection::<SerdeContract>("contracts").insert_one(&contract).unwrap();
-                } 
-                let serde_state = SerdeContractState {
+            TransactionPart::State(contract_state) if !contract_state.is_committed() => {
+                let failed_state = SerdeFailedState {
                     address: contract_state.message.receiver.to_string(),
-                    data: contract_state.data.to_string(),
+                    message: contract_state.message.get_as_block().hash().to_string(),
+                    exit_code: contract_state.exit_code.get_as_block().to_string(),
+                    gas_used: contract_state.gas_used,
                     timestamp: contract_state.message.timestamp,
                 };
-                self.poladb.collection::<SerdeContractState>("contract_states").insert_one(&serde_state).unwrap();
+                self.poladb.collection::<SerdeFailedState>("failed_states").insert_one(&failed_state).unwrap();
+                let message = SerdeMessage::from_message(&contract_state.message);
+                self.poladb.collection::<SerdeMessage>("messages").insert_one(&message).unwrap();
+            },
+            // Программа и данные контракта сохраняются отдельно через save_contract
+            TransactionPart::State(contract_state) => {
+                let message = contract_state.message.clone();
+                let storage = self.poladb.collection::<SerdeStorageEntry>("contract_storage");
+                for (key, value) in contract_state.storage {
+                    let address = contract_state.message.receiver.to_string();
+                    storage.delete_many(doc! { "address": address.clone(), "key": key.to_string() }).unwrap();
+                    if let Some(value) = value {
+                        let entry = SerdeStorageEntry {
+                            address,
+                            key: key.to_string(),
+                            value: value.to_string(),
+                            timestamp: contract_state.message.timestamp,
+                        };
+                        storage.insert_one(&entry).unwrap();
+                    }
+                }
                 let message = SerdeMessage::from_message(&message);
                 self.poladb.collection::<SerdeMessage>("messages").insert_one(&message).unwrap();
                 for child in contract_state.children {
@@ -139,6 +174,19 @@ impl Repository for PolaDBRef {
         }
     }
     
+    // Как и с хранилищем, старая запись удаляется, чтобы по адресу всегда было одно состояние
+    fn save_contract(&mut self, address: Block, program: Option<Block>, data: Block) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        if let Some(program) = program {
+            let contracts = self.poladb.collection::<SerdeContract>("contracts");
+            contracts.delete_many(doc! { "address": address.to_string() }).unwrap();
+            contracts.insert_one(&SerdeContract { address: address.to_string(), program: program.to_string(), timestamp }).unwrap();
+        }
+        let states = self.poladb.collection::<SerdeContractState>("contract_states");
+        states.delete_many(doc! { "address": address.to_string() }).unwrap();
+        states.insert_one(&SerdeContractState { address: address.to_string(), data: data.to_string(), timestamp }).unwrap();
+    }
+
     fn get_all_messages(&self, limit: u64, offset: u64) -> Vec<Message> {
         let mut messages: Vec<SerdeMessage> = self.poladb.collection::<SerdeMessage>("messages").find(doc! {}).run().unwrap().map(|x| x.unwrap()).collect();
         messages.sort_by_key(|x| x.timestamp);
@@ -174,6 +222,11 @@ impl Repository for PolaDBRef {
         }
     }
 
+    fn get_contract_storage(&self, address: Block, key: Block) -> Option<Block> {
+        let storage = self.poladb.collection::<SerdeStorageEntry>("contract_storage");
+        let entry = storage.find_one(doc! { "address": address.to_string(), "key": key.to_string() }).unwrap()?;
+        Block::from_string(entry.value)
+    }
 }
 
 impl PolaDBRef {
@@ -1,11 +1,12 @@
 use std::{io::{BufRead, BufReader, BufWriter, Write}, net::{TcpListener, TcpStream}, time::{SystemTime, UNIX_EPOCH}};
 
-use crate::{repositories::PolaDBRepository, vm::{block::{AsBlock, Block}, builder::Builder, env::{Environment, Repository}, message::{Message, MessageType}}};
+use crate::{repositories::PolaDBRepository, vm::{block::{AsBlock, Block}, builder::Builder, env::{Environment, Limits, Repository}, message::{Message, MessageType}}};
 
 
 pub struct Server {
     repository: PolaDBRepository,
     listener: TcpListener,
+    limits: Limits,
 }
 
 impl Server {
@@ -13,6 +14,7 @@ impl Server {
         Self {
             repository: PolaDBRepository::new(),
             listener: TcpListener::bind("127.0.0.1:4959").unwrap(),
+            limits: Limits::default(),
         }
     }
 
@@ -62,14 +64,15 @@ impl Server {
                                     continue;
                                 },
                                 MessageType::External => {
-                                    let transaction = Environment::start_transaction(message, self.repository.get_ref());
+                                    let transaction = Environment::start_transaction(message, self.repository.get_ref(), self.limits);
                                     let _ = buf_writer.write((transaction.get_as_block().to_string() + "\r\n").as_bytes());
                                     let _ = buf_writer.flush();
                                     continue;
                                 },
                                 MessageType::View => {
-                                    let stack = Environment::view(message, self.repository.get_ref());
+                                    let (exit_code, stack) = Environment::view(message, self.repository.get_ref(), self.limits);
                                     let mut builder = Builder::new();
+                                    builder.write_block(exit_code.get_as_block());
                                     builder.write_u64(stack.len() as u64);
                                     for value in stack {
                                         builder.write_block_with_len(value.get_as_block());
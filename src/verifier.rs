@@ -0,0 +1,134 @@
+use crate::{program::{Program, ProgramError, ProgramReaderFromBytes}, vm::decoder::{decode_all, DecodeError}};
+
+#[derive(Debug)]
+pub enum VerifyError {
+    Load(ProgramError),
+    Decode(usize, DecodeError),
+    BadJump { offset: usize, target: Option<usize> },
+    BadEntrypoint(usize),
+}
+
+impl ToString for VerifyError {
+    fn to_string(&self) -> String {
+        match self {
+            VerifyError::Load(error) => error.to_string(),
+            VerifyError::Decode(offset, DecodeError::UnknownOpcode(opcode)) => format!("unknown opcode {} at {}", opcode, offset),
+            VerifyError::Decode(offset, DecodeError::TruncatedOperand) => format!("truncated operand at {}", offset),
+            VerifyError::BadJump { offset, target: Some(target) } => format!("jump at {} to {} is not an instruction boundary", offset, target),
+            VerifyError::BadJump { offset, target: None } => format!("jump at {} leaves the code", offset),
+            VerifyError::BadEntrypoint(offset) => format!("entrypoint {} is not an instruction boundary", offset),
+        }
+    }
+}
+
+// Проверка программы перед деплоем: весь код разбирается, переходы и точки входа попадают на начало инструкций
+pub fn verify(program: &Program) -> Result<(), VerifyError> {
+    let code = program.get_code();
+    let decoded = decode_all(&code).map_err(|(offset, error)| VerifyError::Decode(offset, error))?;
+    // Конец кода тоже считается границей, переход туда просто завершает выполнение
+    let mut boundaries = vec![false; code.len() + 1];
+    boundaries[code.len()] = true;
+    for instruction in &decoded {
+        boundaries[instruction.offset] = true;
+    }
+    let is_boundary = |offset: usize| boundaries.get(offset).copied().unwrap_or(false);
+    for instruction in &decoded {
+        for target in instruction.targets() {
+            match target {
+                Some(target) if is_boundary(target) => {},
+                _ => return Err(VerifyError::BadJump { offset: instruction.offset, target }),
+            }
+        }
+    }
+    for entrypoint in [program.get_internal(), program.get_external(), program.get_view()] {
+        for offset in entrypoint.get_offsets() {
+            if offset >= code.len() || !is_boundary(offset) {
+                return Err(VerifyError::BadEntrypoint(offset));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn verify_bytes(bytes: &[u8]) -> Result<(), VerifyError> {
+    let program = ProgramReaderFromBytes::new(bytes).load().map_err(VerifyError::Load)?;
+    verify(&program)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{program::{Entrypoint, Program}, vm::instructions};
+
+    use super::{verify, DecodeError, VerifyError};
+
+    fn program(code: Vec<u8>, external: Entrypoint) -> Program {
+        Program::new(code, Entrypoint::none(), external, Entrypoint::none(), Vec::new(), Vec::new())
+    }
+
+    fn fallback(offset: usize) -> Entrypoint {
+        let mut entrypoint = Entrypoint::none();
+        entrypoint.set_fallback(offset);
+        entrypoint
+    }
+
+    #[test]
+    fn accepts_valid_program() {
+        let code = vec![instructions::IPUSH8, 1, instructions::JMP, 0, 0, 0, 0, 0, 0, 0, 11, instructions::HALT];
+        assert!(verify(&program(code, fallback(2))).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_absolute_jump() {
+        // Переход внутрь immediate у IPUSH8
+        let code = vec![instructions::IPUSH8, 1, instructions::JMP, 0, 0, 0, 0, 0, 0, 0, 1];
+        assert!(matches!(verify(&program(code, fallback(0))), Err(VerifyError::BadJump { offset: 2, target: Some(1) })));
+        // Переход дальше конца кода
+        let code = vec![instructions::JMP, 0, 0, 0, 0, 0, 0, 0, 100];
+        assert!(matches!(verify(&program(code, fallback(0))), Err(VerifyError::BadJump { offset: 0, target: Some(100) })));
+    }
+
+    #[test]
+    fn rejects_bad_relative_jump() {
+        // Назад на 5 байт от конца RJMP - до начала кода
+        let code = vec![instructions::RJMP, 0x80, 5];
+        assert!(matches!(verify(&program(code, fallback(0))), Err(VerifyError::BadJump { offset: 0, target: None })));
+        // Вперёд на 1 байт - внутрь immediate у IPUSH8
+        let code = vec![instructions::RJMP, 0, 1, instructions::IPUSH8, 1];
+        assert!(matches!(verify(&program(code, fallback(0))), Err(VerifyError::BadJump { offset: 0, target: Some(4) })));
+    }
+
+    #[test]
+    fn rejects_truncated_immediate() {
+        let code = vec![instructions::HALT, instructions::IPUSH64, 0, 0];
+        assert!(matches!(verify(&program(code, fallback(0))), Err(VerifyError::Decode(1, DecodeError::TruncatedOperand))));
+        let code = vec![instructions::BPUSH, 0, 0, 0, 0, 0, 0, 0, 4, 1, 2];
+        assert!(matches!(verify(&program(code, fallback(0))), Err(VerifyError::Decode(0, DecodeError::TruncatedOperand))));
+    }
+
+    #[test]
+    fn rejects_unknown_opcode() {
+        let code = vec![instructions::HALT, 0xff];
+        assert!(matches!(verify(&program(code, fallback(0))), Err(VerifyError::Decode(1, DecodeError::UnknownOpcode(0xff)))));
+    }
+
+    #[test]
+    fn rejects_out_of_range_entrypoint() {
+        let code = vec![instructions::HALT];
+        assert!(matches!(verify(&program(code, fallback(1))), Err(VerifyError::BadEntrypoint(1))));
+    }
+
+    #[test]
+    fn rejects_mid_instruction_entrypoint() {
+        let code = vec![instructions::IPUSH8, 1, instructions::HALT];
+        assert!(matches!(verify(&program(code, fallback(1))), Err(VerifyError::BadEntrypoint(1))));
+    }
+
+    #[test]
+    fn rejects_bad_handler_offset() {
+        // Запасная точка входа верная, проверяется и таблица обработчиков
+        let mut external = fallback(0);
+        external.add_handler(7, 1);
+        let code = vec![instructions::IPUSH8, 1, instructions::HALT];
+        assert!(matches!(verify(&program(code, external)), Err(VerifyError::BadEntrypoint(1))));
+    }
+}
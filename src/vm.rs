@@ -1,11 +1,16 @@
 use std::usize;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 
-use block::{AsBlock, Block};
+use block::{AsBlock, Block, MAX_BLOCK_SIZE};
 use builder::Builder;
+use memory::Memory;
 use message::{Init, Message};
 use slice::Slice;
 use stack::Stack;
-use utils::{cond_sign, get_relative_reference, get_u16, get_u64, get_u8, operate};
+use trap::{ExitCode, Trap};
+use uint::U256;
+use utils::{cond_sign, get_relative_reference, get_u16, get_u64, get_u8, operate, operate_wide, shift_left, shift_right, signed_div, signed_rem, try_operate};
 
 pub mod block;
 pub mod instructions;
@@ -15,444 +20,1182 @@ pub mod builder;
 pub mod utils;
 pub mod message;
 pub mod env;
+pub mod gas;
+pub mod trap;
+pub mod overlay;
+pub mod uint;
+pub mod decoder;
+pub mod memory;
 
 #[derive(Clone)]
 pub enum Value {
     Number(u64),
+    Wide(U256),
     Block(Block),
     Slice(Slice),
     Builder(Builder),
+    Tuple(Vec<Value>),
+    // Ключи - байты блока, BTreeMap держит их упорядоченными, поэтому сериализация однозначна
+    Map(BTreeMap<Vec<u8>, Value>),
 }
 
-impl ToString for Value {
-    fn to_string(&self) -> String {
+// Глубже вложенные кортежи и словари не создаются ни в VM, ни через from_block.
+// Поэтому рекурсия при сериализации, выводе и удалении значений ограничена
+pub const MAX_VALUE_DEPTH: usize = 64;
+
+// Тег и длина или число в начале сериализованного значения
+const VALUE_HEADER: usize = 1 + size_of::<u64>();
+
+impl Value {
+    // Обратное к get_as_block, блок должен быть прочитан целиком
+    pub fn from_block(block: Block) -> Option<Value> {
+        let mut slice = Slice::new(block);
+        let value = Self::read(&mut slice, 0)?;
+        if slice.len() > 0 {
+            return None;
+        }
+        Some(value)
+    }
+
+    fn read(slice: &mut Slice, depth: usize) -> Option<Value> {
+        let value = match slice.read_u8()? {
+            0 => Value::Number(slice.read_u64()?),
+            1 => Value::Block(slice.read_block_with_len()?),
+            2 => {
+                let position = slice.read_u64()? as usize;
+                Value::Slice(Slice::from_position(slice.read_block_with_len()?, position))
+            },
+            3 => Value::Builder(Builder::from(slice.read_block_with_len()?)),
+            4 => Value::Wide(slice.read_u256()?),
+            5 | 6 if depth >= MAX_VALUE_DEPTH => return None,
+            5 => {
+                let length = slice.read_u64()?;
+                let mut values = Vec::new();
+                for _ in 0..length {
+                    values.push(Self::read(slice, depth + 1)?);
+                }
+                Value::Tuple(values)
+            },
+            6 => {
+                // Ключи должны идти строго по возрастанию, иначе запись не каноническая
+                let length = slice.read_u64()?;
+                let mut map = BTreeMap::new();
+                for _ in 0..length {
+                    let key = slice.read_block_with_len()?.unpack();
+                    if map.last_key_value().is_some_and(|(last, _)| *last >= key) {
+                        return None;
+                    }
+                    let value = Self::read(slice, depth + 1)?;
+                    map.insert(key, value);
+                }
+                Value::Map(map)
+            },
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // Размер после get_as_block и глубина вложенности кортежей и словарей, без сериализации
+    pub fn measure(&self) -> (usize, usize) {
         match self {
-            Value::Number(number) => number.to_string(),
-            Value::Block(block) => format!("[{}]", block.to_string()),
-            Value::Slice(slice) => format!("SLICE {}", slice.len()),
-            Value::Builder(builder) => format!("BUILDER {}", builder.len()),
+            Value::Number(_) => (VALUE_HEADER, 0),
+            Value::Wide(_) => (1 + U256::BYTES, 0),
+            Value::Block(block) => (VALUE_HEADER + block.len(), 0),
+            Value::Slice(slice) => (VALUE_HEADER + size_of::<u64>() + slice.len(), 0),
+            Value::Builder(builder) => (VALUE_HEADER + builder.len(), 0),
+            Value::Tuple(values) => values.iter().fold((VALUE_HEADER, 1), |(size, depth), value| {
+                let (value_size, value_depth) = value.measure();
+                (size + value_size, depth.max(value_depth + 1))
+            }),
+            Value::Map(map) => map.iter().fold((VALUE_HEADER, 1), |(size, depth), (key, value)| {
+                let (value_size, value_depth) = value.measure();
+                (size + size_of::<u64>() + key.len() + value_size, depth.max(value_depth + 1))
+            }),
         }
     }
-}
 
-impl AsBlock for Value {
-    fn get_as_block(&self) -> Block {
-        let mut builder = Builder::new();
+    // Газ за копию значения, числа фиксированного размера копируются бесплатно
+    pub fn copy_cost(&self) -> u64 {
+        match self {
+            Value::Number(_) | Value::Wide(_) => 0,
+            _ => gas::get_copy_cost(self.measure().0),
+        }
+    }
+
+    fn write(&self, builder: &mut Builder) {
         match self {
             Value::Number(number) => {
                 builder.write_u8(0);
-                builder.write_u64(number.clone());
+                builder.write_u64(*number);
             },
             Value::Block(block) => {
                 builder.write_u8(1);
                 builder.write_block_with_len(block.clone());
             },
-            Value::Slice(_) => {
+            Value::Slice(slice) => {
                 builder.write_u8(2);
+                builder.write_u64(slice.position() as u64);
+                builder.write_block_with_len(Block::new(slice.remaining()));
             },
-            Value::Builder(_) => {
+            Value::Builder(value) => {
                 builder.write_u8(3);
+                builder.write_block_with_len(Block::new(value.bytes()));
+            },
+            Value::Wide(number) => {
+                builder.write_u8(4);
+                builder.write_u256(*number);
+            },
+            Value::Tuple(values) => {
+                builder.write_u8(5);
+                builder.write_u64(values.len() as u64);
+                for value in values {
+                    value.write(builder);
+                }
+            },
+            Value::Map(map) => {
+                builder.write_u8(6);
+                builder.write_u64(map.len() as u64);
+                for (key, value) in map {
+                    builder.write_block_with_len(Block::new(key));
+                    value.write(builder);
+                }
             },
         }
+    }
+}
+
+impl ToString for Value {
+    fn to_string(&self) -> String {
+        match self {
+            Value::Number(number) => number.to_string(),
+            Value::Wide(number) => format!("{}w", number.to_string()),
+            Value::Block(block) => format!("[{}]", block.to_string()),
+            Value::Slice(slice) => format!("SLICE {}", slice.len()),
+            Value::Builder(builder) => format!("BUILDER {}", builder.len()),
+            Value::Tuple(values) => format!("({})", values.iter().map(Value::to_string).collect::<Vec<_>>().join(", ")),
+            Value::Map(map) => format!("{{{}}}", map.iter().map(|(key, value)| format!("{}: {}", hex::encode(key), value.to_string())).collect::<Vec<_>>().join(", ")),
+        }
+    }
+}
+
+impl AsBlock for Value {
+    fn get_as_block(&self) -> Block {
+        let mut builder = Builder::new();
+        self.write(&mut builder);
         builder.build()
     }
 }
 
+// Локальные переменные функции: base - индекс первой из них от дна стека значений
+#[derive(Clone, Copy, Default)]
+pub struct Locals {
+    base: usize,
+    length: usize,
+}
+
+// Запись в стеке вызовов: адрес возврата для CALL или обработчик для TRY, вместе с кадром, который нужно вернуть
+#[derive(Clone)]
+pub enum Frame {
+    Return { ip: usize, locals: Locals },
+    Catch { handler: usize, depth: usize, locals: Locals, journal: usize, sent: usize },
+}
+
+// Изменение состояния внутри TRY, которое откатывается при перехвате ошибки: хранит прежнее значение
+#[derive(Clone)]
+enum Change {
+    Data(Block),
+    Storage(Vec<u8>, Option<Option<Block>>),
+}
+
 pub trait SendMessage {
-    fn send_message(&mut self, message: Message);
+    fn send_message(&mut self, message: Message) -> Result<(), Trap>;
+    // Сколько сообщений уже отправлено за запуск
+    fn sent_messages(&self) -> usize;
+    // Отменяет всё, что отправлено после первых count сообщений
+    fn cancel_messages(&mut self, count: usize);
 }
 
+pub trait LoadStorage {
+    fn load_storage(&self, key: Block) -> Option<Block>;
+}
+
+// Всё, что VM нужно от окружения
+pub trait Host: SendMessage + LoadStorage {}
+
+impl<T: SendMessage + LoadStorage> Host for T {}
+
 pub struct VM<'a> {
     pc: usize,
     
     code: Vec<u8>,
+    constants: Block,
     values: Stack<Value>,
-    calls: Stack<usize>,
+    calls: Stack<Frame>,
+    locals: Locals,
+    memory: Memory,
     
     data: Block,
+    // Изменённые за запуск ключи хранилища, None - ключ удалён
+    storage: BTreeMap<Vec<u8>, Option<Block>>,
+    // Журнал ведётся, только пока открыт хотя бы один TRY
+    journal: Vec<Change>,
+    catches: usize,
 
     message: Message,
-    send_message: &'a mut dyn SendMessage,
+    host: &'a mut dyn Host,
     
+    gas_limit: u64,
+    gas_used: u64,
+
     stopped: bool,
 }
 
 // Impl для того чтоб в стеке можно сразу получить по типу, для уменьшение кода
 impl Stack<Value> {
-    pub fn get_number(&self, offset: usize) -> Option<u64> {
-        if let Some(value) = self.get(offset) {
-            if let Value::Number(number) = value {
-                Some(number.clone())
-            } else {
-                None
-            }
-        } else {
-            None
+    pub fn get_value(&self, offset: usize) -> Result<&Value, Trap> {
+        self.get(offset).ok_or(Trap::StackUnderflow)
+    }
+
+    pub fn get_number(&self, offset: usize) -> Result<u64, Trap> {
+        match self.get_value(offset)? {
+            Value::Number(number) => Ok(*number),
+            _ => Err(Trap::TypeError),
         }
     }
 
-    pub fn get_block(&self, offset: usize) -> Option<Block> {
-        if let Some(value) = self.get(offset) {
-            if let Value::Block(block) = value {
-                Some(block.clone())
-            } else {
-                None
-            }
-        } else {
-            None
+    // u64 расширяется до U256
+    pub fn get_wide(&self, offset: usize) -> Result<U256, Trap> {
+        match self.get_value(offset)? {
+            Value::Number(number) => Ok(U256::from_u64(*number)),
+            Value::Wide(number) => Ok(*number),
+            _ => Err(Trap::TypeError),
         }
     }
 
-    pub fn get_mut_slice(&mut self, offset: usize) -> Option<&mut Slice> {
-        if let Some(value) = self.get_mut(offset) {
-            if let Value::Slice(block) = value {
-                Some(block)
-            } else {
-                None
-            }
-        } else {
-            None
+    pub fn is_narrow_pair(&self) -> bool {
+        matches!(self.pair(), Some((Value::Number(_), Value::Number(_))))
+    }
+
+    pub fn get_block(&self, offset: usize) -> Result<Block, Trap> {
+        match self.get_value(offset)? {
+            Value::Block(block) => Ok(block.clone()),
+            _ => Err(Trap::TypeError),
         }
     }
 
-    pub fn get_mut_builder(&mut self, offset: usize) -> Option<&mut Builder> {
-        if let Some(value) = self.get_mut(offset) {
-            if let Value::Builder(block) = value {
-                Some(block)
-            } else {
-                None
-            }
+    pub fn get_mut_slice(&mut self, offset: usize) -> Result<&mut Slice, Trap> {
+        match self.get_mut(offset).ok_or(Trap::StackUnderflow)? {
+            Value::Slice(slice) => Ok(slice),
+            _ => Err(Trap::TypeError),
+        }
+    }
+
+    pub fn get_mut_builder(&mut self, offset: usize) -> Result<&mut Builder, Trap> {
+        match self.get_mut(offset).ok_or(Trap::StackUnderflow)? {
+            Value::Builder(builder) => Ok(builder),
+            _ => Err(Trap::TypeError),
+        }
+    }
+
+    pub fn get_mut_tuple(&mut self, offset: usize) -> Result<&mut Vec<Value>, Trap> {
+        match self.get_mut(offset).ok_or(Trap::StackUnderflow)? {
+            Value::Tuple(values) => Ok(values),
+            _ => Err(Trap::TypeError),
+        }
+    }
+
+    pub fn get_mut_map(&mut self, offset: usize) -> Result<&mut BTreeMap<Vec<u8>, Value>, Trap> {
+        match self.get_mut(offset).ok_or(Trap::StackUnderflow)? {
+            Value::Map(map) => Ok(map),
+            _ => Err(Trap::TypeError),
+        }
+    }
+
+    pub fn require(&self, length: usize) -> Result<(), Trap> {
+        if self.len() >= length {
+            Ok(())
         } else {
-            None
+            Err(Trap::StackUnderflow)
         }
     }
 }
 
 impl<'a> VM<'a> {
-    pub fn new(code: Vec<u8>, pc: usize, data: Block, message: Message, send_message: &'a mut dyn SendMessage) -> Self {
+    pub fn new(code: Vec<u8>, pc: usize, constants: Block, data: Block, message: Message, gas_limit: u64, host: &'a mut dyn Host) -> Self {
         Self {
             pc,
             stopped: true,
             code,
+            constants,
             values: Stack::new(),
             calls: Stack::new(),
+            locals: Locals::default(),
+            memory: Memory::new(),
             data,
+            storage: BTreeMap::new(),
+            journal: Vec::new(),
+            catches: 0,
             message,
-            send_message,
+            host,
+            gas_limit,
+            gas_used: 0,
         }
     }
 
     pub fn next(&mut self, length: usize) -> Option<&[u8]> {
-        let slice = self.code.get(self.pc..(self.pc+length))?;
+        let slice = self.code.get(self.pc..self.pc.checked_add(length)?)?;
         self.pc += length;
         Some(slice)
     }
 
-    pub fn next_u64(&mut self) -> Option<u64> {
-        get_u64(self.next(size_of::<u64>())?)
+    pub fn next_u64(&mut self) -> Result<u64, Trap> {
+        self.next(size_of::<u64>()).and_then(get_u64).ok_or(Trap::TruncatedOperand)
+    }
+
+    pub fn next_u8(&mut self) -> Result<u8, Trap> {
+        self.next(size_of::<u8>()).and_then(get_u8).ok_or(Trap::TruncatedOperand)
+    }
+
+    pub fn next_u16(&mut self) -> Result<u16, Trap> {
+        self.next(size_of::<u16>()).and_then(get_u16).ok_or(Trap::TruncatedOperand)
     }
 
-    pub fn next_u8(&mut self) -> Option<u8> {
-        get_u8(self.next(size_of::<u8>())?)
+    fn jump(&mut self, reference: usize) -> Result<(), Trap> {
+        if reference > self.code.len() {
+            return Err(Trap::BadJump);
+        }
+        self.pc = reference;
+        Ok(())
     }
 
-    pub fn next_u16(&mut self) -> Option<u16> {
-        get_u16(self.next(size_of::<u16>())?)
+    fn relative_jump(&mut self, reference: u16) -> Result<(), Trap> {
+        let (direction, offset) = get_relative_reference(reference);
+        let reference = match direction {
+            utils::Direction::Forward => self.pc.checked_add(offset as usize),
+            utils::Direction::Backward => self.pc.checked_sub(offset as usize),
+        };
+        self.jump(reference.ok_or(Trap::BadJump)?)
     }
 
-    fn execute(&mut self, opcode: u8) {
+    fn execute(&mut self, opcode: u8) -> Result<(), Trap> {
         if opcode == instructions::IPUSH64 {
-            if let Some(value) = self.next_u64() {
-                self.values.push(Value::Number(value));
-            }
+            let value = self.next_u64()?;
+            self.values.push(Value::Number(value));
         } else if opcode == instructions::IPUSH8 {
-            if let Some(value) = self.next_u8() {
-                self.values.push(Value::Number(value as u64));
-            }
+            let value = self.next_u8()?;
+            self.values.push(Value::Number(value as u64));
         } else if opcode == instructions::SPUSH {
-            if let Some(offset) = self.next_u16() {
-                if let Some(stacked_value) = self.values.get(offset as usize) {
-                    self.values.push(stacked_value.clone());
-                }
-            }
+            let offset = self.next_u16()?;
+            let stacked_value = self.copy_value(offset as usize)?;
+            self.values.push(stacked_value);
         } else if opcode == instructions::DROPN {
-            if let Some(length) = self.next_u16() {
-                self.values.drop(length as usize);
-            }
+            let length = self.next_u16()?;
+            self.values.require(length as usize)?;
+            self.values.drop(length as usize);
         } else if opcode == instructions::CHG {
-            let first = self.next_u16();
-            let second = self.next_u16();
-            if first.is_some() && second.is_some() {
-                let first = first.unwrap();
-                let second = second.unwrap();
-                self.values.change(first as usize, second as usize);
-            }
+            let first = self.next_u16()?;
+            let second = self.next_u16()?;
+            self.values.require(first.max(second) as usize + 1)?;
+            self.values.change(first as usize, second as usize);
         } else if opcode == instructions::SWAP {
+            self.values.require(2)?;
             self.values.change(0, 1);
         } else if opcode == instructions::BPUSH {
-            if let Some(size) = self.next_u64() {
-                if let Some(block) = self.next(size as usize).map(|x| Block::from(x)) {
-                    self.values.push(Value::Block(block));
-                }
-            }
+            let size = self.next_u64()? as usize;
+            self.charge_copy(size, size)?;
+            let block = self.next(size).map(Block::from).ok_or(Trap::TruncatedOperand)?;
+            self.values.push(Value::Block(block));
         } else if opcode == instructions::BHASH {
-            if let Some(block) = self.values.get_block(0) {
-                match block.hash() {
-                    hash => {
-                        self.values.pop();
-                        self.values.push(Value::Block(hash));
-                    }
-                }
-            }
+            let block = self.values.get_block(0)?;
+            self.charge(gas::get_copy_cost(block.len()))?;
+            let hash = block.hash();
+            self.values.pop();
+            self.values.push(Value::Block(hash));
         } else if opcode == instructions::BLEN {
-            if let Some(block) = self.values.get_block(0) {
-                match block.len() {
-                    length => {
-                        self.values.push(Value::Number(length as u64));
-                    }
-                }
-            }
+            let length = self.values.get_block(0)?.len();
+            self.values.push(Value::Number(length as u64));
         } else if opcode == instructions::MKSLICE {
-            if let Some(block) = self.values.get_block(0) {
-                self.values.pop();
-                self.values.push(Value::Slice(Slice::new(block)));
-            }
+            let block = self.values.get_block(0)?;
+            self.charge(gas::get_copy_cost(block.len()))?;
+            self.values.pop();
+            self.values.push(Value::Slice(Slice::new(block)));
         } else if opcode == instructions::IREAD64 {
-            if let Some(slice) = self.values.get_mut_slice(0) {
-                if let Some(value) = slice.read_u64() {
-                    self.values.push(Value::Number(value));
-                }
-            }
+            let value = self.values.get_mut_slice(0)?.read_u64().ok_or(Trap::SliceUnderflow)?;
+            self.values.push(Value::Number(value));
         } else if opcode == instructions::IREAD8 {
-            if let Some(slice) = self.values.get_mut_slice(0) {
-                if let Some(value) = slice.read_u8() {
-                    self.values.push(Value::Number(value as u64));
-                }
-            }
+            let value = self.values.get_mut_slice(0)?.read_u8().ok_or(Trap::SliceUnderflow)?;
+            self.values.push(Value::Number(value as u64));
         } else if opcode == instructions::BREAD {
-            let length = self.values.get_number(0);
-            if let Some(length) = length  {
-                if let Some(slice) = self.values.get_mut_slice(1) {
-                    if let Some(block) = slice.read_block(length as usize) {
-                        self.values.pop();
-                        self.values.push(Value::Block(block));
-                    }
-                }
-            }
+            let length = self.values.get_number(0)?;
+            let block = self.values.get_mut_slice(1)?.read_block(length as usize).ok_or(Trap::SliceUnderflow)?;
+            self.values.pop();
+            self.values.push(Value::Block(block));
         } else if opcode == instructions::SLLEN {
-            if let Some(slice) = self.values.get_mut_slice(0) {
-                match slice.len() {
-                    length => {
-                        self.values.push(Value::Number(length as u64));
-                    }
-                }
-            }
+            let length = self.values.get_mut_slice(0)?.len();
+            self.values.push(Value::Number(length as u64));
         } else if opcode == instructions::MKBUILDER {
             self.values.push(Value::Builder(Builder::new()));
         } else if opcode == instructions::IWRITE8 {
-            if let Some(value) = self.values.get_number(0) {
-                if let Some(builder) = self.values.get_mut_builder(1) {
-                    builder.write_u8(value as u8);
-                    self.values.pop();
-                }
-            }
+            let value = self.values.get_number(0)?;
+            self.values.get_mut_builder(1)?.write_u8(value as u8);
+            self.values.pop();
         } else if opcode == instructions::IWRITE64 {
-            if let Some(value) = self.values.get_number(0) {
-                if let Some(builder) = self.values.get_mut_builder(1) {
-                    builder.write_u64(value);
-                    self.values.pop();
-                }
-            }
+            let value = self.values.get_number(0)?;
+            self.values.get_mut_builder(1)?.write_u64(value);
+            self.values.pop();
         } else if opcode == instructions::BWRITE {
-            if let Some(value) = self.values.get_block(0) {
-                if let Some(builder) = self.values.get_mut_builder(1) {
-                    builder.write_block(value);
-                    self.values.pop();
-                }
-            }
+            let value = self.values.get_block(0)?;
+            let length = self.values.get_mut_builder(1)?.len() + value.len();
+            self.charge_copy(value.len(), length)?;
+            self.values.get_mut_builder(1)?.write_block(value);
+            self.values.pop();
         } else if opcode == instructions::BUILD {
-            if let Some(builder) = self.values.get_mut_builder(0) {
-                let block = builder.build();
-                self.values.pop();
-                self.values.push(Value::Block(block));
-            }
+            let length = self.values.get_mut_builder(0)?.len();
+            self.charge_copy(length, length)?;
+            let block = self.values.get_mut_builder(0)?.build();
+            self.values.pop();
+            self.values.push(Value::Block(block));
         } else if opcode == instructions::BLLEN {
-            if let Some(builder) = self.values.get_mut_builder(0) {
-                match builder.len() {
-                    length => {
-                        self.values.push(Value::Number(length as u64));
-                    }
-                }
-            }
+            let length = self.values.get_mut_builder(0)?.len();
+            self.values.push(Value::Number(length as u64));
         } else if opcode == instructions::ADD {
-            if let Some(value) = operate(self.values.pair(), |a, b| a + b) {
-                self.values.pop_pair();
-                self.values.push(Value::Number(value));
-            }
+            self.arithmetic(|a, b| a.checked_add(b).ok_or(Trap::Overflow), |a, b| a.checked_add(b).ok_or(Trap::Overflow))?;
         } else if opcode == instructions::SUB {
-            if let Some(value) = operate(self.values.pair(), |a, b| a - b) {
-                self.values.pop_pair();
-                self.values.push(Value::Number(value));
-            }
+            self.arithmetic(|a, b| a.checked_sub(b).ok_or(Trap::Overflow), |a, b| a.checked_sub(b).ok_or(Trap::Overflow))?;
         } else if opcode == instructions::MUL {
-            if let Some(value) = operate(self.values.pair(), |a, b| a * b) {
-                self.values.pop_pair();
-                self.values.push(Value::Number(value));
-            }
+            self.arithmetic(|a, b| a.checked_mul(b).ok_or(Trap::Overflow), |a, b| a.checked_mul(b).ok_or(Trap::Overflow))?;
         } else if opcode == instructions::DIV {
-            if let Some(value) = operate(self.values.pair(), |a, b| a / b) {
-                self.values.pop_pair();
-                self.values.push(Value::Number(value));
-            }
+            self.arithmetic(|a, b| a.checked_div(b).ok_or(Trap::DivisionByZero), |a, b| a.checked_div(b).ok_or(Trap::DivisionByZero))?;
         } else if opcode == instructions::MOD {
-            if let Some(value) = operate(self.values.pair(), |a, b| a % b) {
-                self.values.pop_pair();
-                self.values.push(Value::Number(value));
-            }
+            self.arithmetic(|a, b| a.checked_rem(b).ok_or(Trap::DivisionByZero), |a, b| a.checked_rem(b).ok_or(Trap::DivisionByZero))?;
+        } else if opcode == instructions::ADDW {
+            self.arithmetic(|a, b| Ok(a.wrapping_add(b)), |a, b| Ok(a.wrapping_add(b)))?;
+        } else if opcode == instructions::SUBW {
+            self.arithmetic(|a, b| Ok(a.wrapping_sub(b)), |a, b| Ok(a.wrapping_sub(b)))?;
+        } else if opcode == instructions::MULW {
+            self.arithmetic(|a, b| Ok(a.wrapping_mul(b)), |a, b| Ok(a.wrapping_mul(b)))?;
+        } else if opcode == instructions::ADDS {
+            self.arithmetic(|a, b| Ok(a.saturating_add(b)), |a, b| Ok(a.saturating_add(b)))?;
+        } else if opcode == instructions::SUBS {
+            self.arithmetic(|a, b| Ok(a.saturating_sub(b)), |a, b| Ok(a.saturating_sub(b)))?;
+        } else if opcode == instructions::MULS {
+            self.arithmetic(|a, b| Ok(a.saturating_mul(b)), |a, b| Ok(a.saturating_mul(b)))?;
         } else if opcode == instructions::INC {
-            if let Some(value) = self.values.get_mut(0) {
-                if let Value::Number(number) = value {
-                    *number += 1;
-                }
+            match self.values.get_mut(0).ok_or(Trap::StackUnderflow)? {
+                Value::Number(number) => *number = number.checked_add(1).ok_or(Trap::Overflow)?,
+                Value::Wide(number) => *number = number.checked_add(U256::ONE).ok_or(Trap::Overflow)?,
+                _ => return Err(Trap::TypeError),
             }
         } else if opcode == instructions::CMB {
-            if let Some(value) = operate(self.values.pair(), |a, b| cond_sign(a > b)) {
-                self.values.pop_pair();
-                self.values.push(Value::Number(value));
-            }
+            self.compare(Ordering::is_gt)?;
         } else if opcode == instructions::CML {
-            if let Some(value) = operate(self.values.pair(), |a, b| cond_sign(a < b)) {
-                self.values.pop_pair();
-                self.values.push(Value::Number(value));
-            }
+            self.compare(Ordering::is_lt)?;
         } else if opcode == instructions::CMBE {
-            if let Some(value) = operate(self.values.pair(), |a, b| cond_sign(a >= b)) {
-                self.values.pop_pair();
-                self.values.push(Value::Number(value));
-            }
+            self.compare(Ordering::is_ge)?;
         } else if opcode == instructions::CMLE {
-            if let Some(value) = operate(self.values.pair(), |a, b| cond_sign(a <= b)) {
-                self.values.pop_pair();
-                self.values.push(Value::Number(value));
-            }
+            self.compare(Ordering::is_le)?;
         } else if opcode == instructions::CME {
-            if let Some(value) = operate(self.values.pair(), |a, b| cond_sign(a == b)) {
-                self.values.pop_pair();
-                self.values.push(Value::Number(value));
-            }
+            self.compare(Ordering::is_eq)?;
         } else if opcode == instructions::CMNE {
-            if let Some(value) = operate(self.values.pair(), |a, b| cond_sign(a != b)) {
-                self.values.pop_pair();
-                self.values.push(Value::Number(value));
-            }
+            self.compare(Ordering::is_ne)?;
+        } else if opcode == instructions::AND {
+            self.arithmetic(|a, b| Ok(a & b), |a, b| Ok(a & b))?;
+        } else if opcode == instructions::OR {
+            self.arithmetic(|a, b| Ok(a | b), |a, b| Ok(a | b))?;
+        } else if opcode == instructions::XOR {
+            self.arithmetic(|a, b| Ok(a ^ b), |a, b| Ok(a ^ b))?;
+        } else if opcode == instructions::SHL {
+            let shift = self.values.get_number(0)?;
+            let value = match self.values.get_value(1)? {
+                Value::Number(value) => Value::Number(shift_left(*value, shift)),
+                Value::Wide(value) => Value::Wide(*value << shift),
+                _ => return Err(Trap::TypeError),
+            };
+            self.values.pop_pair();
+            self.values.push(value);
+        } else if opcode == instructions::SHR {
+            let shift = self.values.get_number(0)?;
+            let value = match self.values.get_value(1)? {
+                Value::Number(value) => Value::Number(shift_right(*value, shift)),
+                Value::Wide(value) => Value::Wide(*value >> shift),
+                _ => return Err(Trap::TypeError),
+            };
+            self.values.pop_pair();
+            self.values.push(value);
+        } else if opcode == instructions::NOT {
+            let value = match self.values.get_value(0)? {
+                Value::Number(value) => Value::Number(!value),
+                Value::Wide(value) => Value::Wide(!*value),
+                _ => return Err(Trap::TypeError),
+            };
+            self.values.pop();
+            self.values.push(value);
+        } else if opcode == instructions::LNOT {
+            let value = match self.values.get_value(0)? {
+                Value::Number(value) => cond_sign(*value == 0),
+                Value::Wide(value) => cond_sign(value.is_zero()),
+                _ => return Err(Trap::TypeError),
+            };
+            self.values.pop();
+            self.values.push(Value::Number(value));
         } else if opcode == instructions::JMP {
-            if let Some(reference) = self.next_u64() {
-                self.pc = reference as usize;
-            }
+            let reference = self.next_u64()?;
+            self.jump(reference as usize)?;
         } else if opcode == instructions::JMT {
-            if let Some(reference) = self.next_u64() {
-                if let Some(value) = self.values.get_number(0) {
-                    if value != 0 {
-                        self.pc = reference as usize;
-                    }
-                    self.values.drop(1);
-                }
+            let reference = self.next_u64()?;
+            let value = self.values.get_number(0)?;
+            self.values.drop(1);
+            if value != 0 {
+                self.jump(reference as usize)?;
             }
         } else if opcode == instructions::JMF {
-            if let Some(reference) = self.next_u64() {
-                if let Some(value) = self.values.get_number(0) {
-                    if value == 0 {
-                        self.pc = reference as usize;
-                    }
-                    self.values.drop(1);
-                }
+            let reference = self.next_u64()?;
+            let value = self.values.get_number(0)?;
+            self.values.drop(1);
+            if value == 0 {
+                self.jump(reference as usize)?;
             }
         } else if opcode == instructions::RJMP {
-            if let Some(reference) = self.next_u16() {
-                let (direction, offset) = get_relative_reference(reference);
-                match direction {
-                    utils::Direction::Forward => self.pc += offset as usize,
-                    utils::Direction::Backward => self.pc -= offset as usize,
-                }
-            }
+            let reference = self.next_u16()?;
+            self.relative_jump(reference)?;
         } else if opcode == instructions::RJMT {
-            if let Some(reference) = self.next_u16() {
-                if let Some(value) = self.values.get_number(0) {
-                    if value != 0 {
-                        let (direction, offset) = get_relative_reference(reference);
-                        match direction {
-                            utils::Direction::Forward => self.pc += offset as usize,
-                            utils::Direction::Backward => self.pc -= offset as usize,
-                        }
-                    }
-                    self.values.drop(1);
-                }
+            let reference = self.next_u16()?;
+            let value = self.values.get_number(0)?;
+            self.values.drop(1);
+            if value != 0 {
+                self.relative_jump(reference)?;
             }
         } else if opcode == instructions::RJMF {
-            if let Some(reference) = self.next_u16() {
-                if let Some(value) = self.values.get_number(0) {
-                    if value == 0 {
-                        let (direction, offset) = get_relative_reference(reference);
-                        match direction {
-                            utils::Direction::Forward => self.pc += offset as usize,
-                            utils::Direction::Backward => self.pc -= offset as usize,
-                        }
-                    }
-                    self.values.drop(1);
-                }
+            let reference = self.next_u16()?;
+            let value = self.values.get_number(0)?;
+            self.values.drop(1);
+            if value == 0 {
+                self.relative_jump(reference)?;
             }
         } else if opcode == instructions::HALT {
             self.stopped = true;
         } else if opcode == instructions::CALL {
-            if let Some(ip) = self.next_u64() {
-                self.calls.push(self.pc);
-                self.pc = ip as usize;
-            }
+            let ip = self.next_u64()?;
+            self.calls.push(Frame::Return { ip: self.pc, locals: self.locals });
+            self.locals = Locals::default();
+            self.jump(ip as usize)?;
         } else if opcode == instructions::RET {
-            if let Some(ip) = self.calls.pop() {
-                self.pc = ip;
+            // Незакрытые TRY внутри функции снимаются вместе с ней
+            loop {
+                match self.calls.pop().ok_or(Trap::StackUnderflow)? {
+                    Frame::Return { ip, locals } => {
+                        self.pc = ip;
+                        self.locals = locals;
+                        break;
+                    },
+                    Frame::Catch { .. } => self.leave_try(),
+                }
             }
         } else if opcode == instructions::LDATA {
+            // Данные приходят из хранилища, поэтому размер проверяется при чтении
+            self.charge_copy(self.data.len(), self.data.len())?;
             self.values.push(Value::Block(self.data.clone()));
         } else if opcode == instructions::SDATA {
-            if let Some(block) = self.values.get_block(0) {
-                self.data = block;
-                self.values.pop();
-            }
+            let data = self.values.get_block(0)?;
+            let previous = std::mem::replace(&mut self.data, data);
+            self.record(Change::Data(previous));
+            self.values.pop();
         } else if opcode == instructions::MESSAGE {
-            self.values.push(Value::Block(self.message.get_as_block()));
+            let block = self.message.get_as_block();
+            self.charge_copy(block.len(), block.len())?;
+            self.values.push(Value::Block(block));
         } else if opcode == instructions::SEND {
-            let receiver = self.values.get_block(3);
-            let init_block = self.values.get_block(2);
-            let opcode = self.values.get_number(1);
-            let body = self.values.get_block(0);
-            if receiver.is_some() && init_block.is_some() && opcode.is_some() && body.is_some() {
-                let init = Init::from_block(init_block.unwrap());
-                self.send_message.send_message(Message::new(
-                    message::MessageType::Internal,
-                    body.unwrap(),
-                    opcode.unwrap(),
-                    self.message.receiver.clone(),
-                    receiver.unwrap(),
-                    init,
-                ));
-                self.values.drop(4);
+            let receiver = self.values.get_block(3)?;
+            let init_block = self.values.get_block(2)?;
+            let opcode = self.values.get_number(1)?;
+            let body = self.values.get_block(0)?;
+            let init = Init::from_block(init_block);
+            self.host.send_message(Message::new(
+                message::MessageType::Internal,
+                body,
+                opcode,
+                self.message.receiver.clone(),
+                receiver,
+                init,
+                self.gas_limit - self.gas_used,
+            ))?;
+            self.values.drop(4);
+        } else if opcode == instructions::THROW {
+            let code = self.next_u16()?;
+            return Err(Trap::Throw(code as u64));
+        } else if opcode == instructions::THROWIF {
+            let code = self.next_u16()?;
+            let value = self.values.get_number(0)?;
+            self.values.drop(1);
+            if value != 0 {
+                return Err(Trap::Throw(code as u64));
+            }
+        } else if opcode == instructions::THROWIFNOT {
+            let code = self.next_u16()?;
+            let value = self.values.get_number(0)?;
+            self.values.drop(1);
+            if value == 0 {
+                return Err(Trap::Throw(code as u64));
+            }
+        } else if opcode == instructions::TRY {
+            let handler = self.next_u64()? as usize;
+            if handler > self.code.len() {
+                return Err(Trap::BadJump);
+            }
+            self.calls.push(Frame::Catch { handler, depth: self.values.len(), locals: self.locals, journal: self.journal.len(), sent: self.host.sent_messages() });
+            self.catches += 1;
+        } else if opcode == instructions::ENDTRY {
+            match self.calls.pop() {
+                Some(Frame::Catch { .. }) => self.leave_try(),
+                _ => return Err(Trap::StackUnderflow),
+            }
+        } else if opcode == instructions::IPUSH256 {
+            let bytes = self.next(U256::BYTES).ok_or(Trap::TruncatedOperand)?;
+            let value = U256::from_be_slice(bytes).ok_or(Trap::TruncatedOperand)?;
+            self.values.push(Value::Wide(value));
+        } else if opcode == instructions::IREAD256 {
+            let value = self.values.get_mut_slice(0)?.read_u256().ok_or(Trap::SliceUnderflow)?;
+            self.values.push(Value::Wide(value));
+        } else if opcode == instructions::IWRITE256 {
+            let value = self.values.get_wide(0)?;
+            self.values.get_mut_builder(1)?.write_u256(value);
+            self.values.pop();
+        } else if opcode == instructions::WIDEN {
+            let value = self.values.get_wide(0)?;
+            self.values.pop();
+            self.values.push(Value::Wide(value));
+        } else if opcode == instructions::NARROW {
+            let value = self.values.get_wide(0)?.to_u64().ok_or(Trap::Overflow)?;
+            self.values.pop();
+            self.values.push(Value::Number(value));
+        } else if opcode == instructions::BTOW {
+            let block = self.values.get_block(0)?;
+            let value = U256::from_be_slice(&block.unpack()).ok_or(Trap::Overflow)?;
+            self.values.pop();
+            self.values.push(Value::Wide(value));
+        } else if opcode == instructions::WTOB {
+            let value = self.values.get_wide(0)?;
+            self.values.pop();
+            self.values.push(Value::Block(Block::new(&value.to_be_bytes())));
+        } else if opcode == instructions::SCMB {
+            let value = operate(self.values.pair(), |a, b| cond_sign((a as i64) > (b as i64)))?;
+            self.values.pop_pair();
+            self.values.push(Value::Number(value));
+        } else if opcode == instructions::SCML {
+            let value = operate(self.values.pair(), |a, b| cond_sign((a as i64) < (b as i64)))?;
+            self.values.pop_pair();
+            self.values.push(Value::Number(value));
+        } else if opcode == instructions::SCMBE {
+            let value = operate(self.values.pair(), |a, b| cond_sign((a as i64) >= (b as i64)))?;
+            self.values.pop_pair();
+            self.values.push(Value::Number(value));
+        } else if opcode == instructions::SCMLE {
+            let value = operate(self.values.pair(), |a, b| cond_sign((a as i64) <= (b as i64)))?;
+            self.values.pop_pair();
+            self.values.push(Value::Number(value));
+        } else if opcode == instructions::SDIV {
+            let value = try_operate(self.values.pair(), signed_div)?;
+            self.values.pop_pair();
+            self.values.push(Value::Number(value));
+        } else if opcode == instructions::SMOD {
+            let value = try_operate(self.values.pair(), signed_rem)?;
+            self.values.pop_pair();
+            self.values.push(Value::Number(value));
+        } else if opcode == instructions::NEG {
+            let value = (self.values.get_number(0)? as i64).checked_neg().ok_or(Trap::Overflow)?;
+            self.values.pop();
+            self.values.push(Value::Number(value as u64));
+        } else if opcode == instructions::ABS {
+            let value = (self.values.get_number(0)? as i64).checked_abs().ok_or(Trap::Overflow)?;
+            self.values.pop();
+            self.values.push(Value::Number(value as u64));
+        } else if opcode == instructions::IREAD8S {
+            let value = self.values.get_mut_slice(0)?.read_u8().ok_or(Trap::SliceUnderflow)?;
+            self.values.push(Value::Number(value as i8 as i64 as u64));
+        } else if opcode == instructions::IREAD16S {
+            let value = self.values.get_mut_slice(0)?.read_u16().ok_or(Trap::SliceUnderflow)?;
+            self.values.push(Value::Number(value as i16 as i64 as u64));
+        } else if opcode == instructions::IREAD32S {
+            let value = self.values.get_mut_slice(0)?.read_u32().ok_or(Trap::SliceUnderflow)?;
+            self.values.push(Value::Number(value as i32 as i64 as u64));
+        } else if opcode == instructions::DUP {
+            let value = self.copy_value(0)?;
+            self.values.push(value);
+        } else if opcode == instructions::OVER {
+            let value = self.copy_value(1)?;
+            self.values.push(value);
+        } else if opcode == instructions::ROT {
+            let value = self.values.remove(2).ok_or(Trap::StackUnderflow)?;
+            self.values.push(value);
+        } else if opcode == instructions::NIP {
+            self.values.remove(1).ok_or(Trap::StackUnderflow)?;
+        } else if opcode == instructions::TUCK {
+            self.values.require(2)?;
+            let value = self.copy_value(0)?;
+            self.values.insert(2, value);
+        } else if opcode == instructions::PICK {
+            let offset = self.values.get_number(0)?;
+            let value = self.copy_value((offset as usize).saturating_add(1))?;
+            self.values.pop();
+            self.values.push(value);
+        } else if opcode == instructions::ROLL {
+            let offset = self.values.get_number(0)?;
+            self.values.require((offset as usize).saturating_add(2))?;
+            self.values.pop();
+            let value = self.values.remove(offset as usize).ok_or(Trap::StackUnderflow)?;
+            self.values.push(value);
+        } else if opcode == instructions::DEPTH {
+            let depth = self.values.len();
+            self.values.push(Value::Number(depth as u64));
+        } else if opcode == instructions::BEQ {
+            let first = self.values.get_block(1)?;
+            let second = self.values.get_block(0)?;
+            self.values.pop_pair();
+            self.values.push(Value::Number(cond_sign(first.bytes() == second.bytes())));
+        } else if opcode == instructions::BCMP {
+            let first = self.values.get_block(1)?;
+            let second = self.values.get_block(0)?;
+            let value = match first.bytes().cmp(second.bytes()) {
+                Ordering::Less => -1i64 as u64,
+                Ordering::Equal => 0,
+                Ordering::Greater => 1,
+            };
+            self.values.pop_pair();
+            self.values.push(Value::Number(value));
+        } else if opcode == instructions::BCAT {
+            let first = self.values.get_block(1)?;
+            let second = self.values.get_block(0)?;
+            let length = first.len() + second.len();
+            self.charge_copy(length, length)?;
+            self.values.pop_pair();
+            self.values.push(Value::Block(first.concat(&second)));
+        } else if opcode == instructions::BSUB {
+            let block = self.values.get_block(2)?;
+            let offset = self.values.get_number(1)?;
+            let length = self.values.get_number(0)?;
+            let block = block.sub(offset as usize, length as usize).ok_or(Trap::SliceUnderflow)?;
+            self.values.drop(3);
+            self.values.push(Value::Block(block));
+        } else if opcode == instructions::BTON {
+            let block = self.values.get_block(0)?;
+            if block.len() > size_of::<u64>() {
+                return Err(Trap::Overflow);
+            }
+            let value = block.bytes().iter().fold(0u64, |value, byte| (value << 8) | *byte as u64);
+            self.values.pop();
+            self.values.push(Value::Number(value));
+        } else if opcode == instructions::NTOB {
+            let value = self.values.get_number(0)?;
+            self.values.pop();
+            self.values.push(Value::Block(Block::new(&value.to_be_bytes())));
+        } else if opcode == instructions::MSENDER {
+            self.values.push(Value::Block(self.message.sender.clone()));
+        } else if opcode == instructions::MSELF {
+            self.values.push(Value::Block(self.message.receiver.clone()));
+        } else if opcode == instructions::MOPCODE {
+            self.values.push(Value::Number(self.message.opcode));
+        } else if opcode == instructions::MBODY {
+            self.charge_copy(self.message.body.len(), self.message.body.len())?;
+            self.values.push(Value::Block(self.message.body.clone()));
+        } else if opcode == instructions::MTIME {
+            self.values.push(Value::Number(self.message.timestamp));
+        } else if opcode == instructions::MTYPE {
+            self.values.push(Value::Number(self.message.message_type.to_u8() as u64));
+        } else if opcode == instructions::MHASH {
+            let block = self.message.get_as_block();
+            self.charge(gas::get_copy_cost(block.len()))?;
+            self.values.push(Value::Block(block.hash()));
+        } else if opcode == instructions::LCONST {
+            self.charge_copy(self.constants.len(), self.constants.len())?;
+            self.values.push(Value::Block(self.constants.clone()));
+        } else if opcode == instructions::ENTER {
+            // Верхние length значений (аргументы и заготовки под переменные) становятся локальными
+            let length = self.next_u16()? as usize;
+            self.values.require(length)?;
+            self.locals = Locals { base: self.values.len() - length, length };
+        } else if opcode == instructions::LLOAD {
+            let index = self.next_u16()?;
+            let index = self.local_index(index)?;
+            let cost = self.values.get_at(index).ok_or(Trap::BadLocal)?.copy_cost();
+            self.charge(cost)?;
+            let value = self.values.get_at(index).ok_or(Trap::BadLocal)?.clone();
+            self.values.push(value);
+        } else if opcode == instructions::LSTORE {
+            let index = self.next_u16()?;
+            let index = self.local_index(index)?;
+            self.values.require(1)?;
+            if index + 1 >= self.values.len() {
+                return Err(Trap::BadLocal);
+            }
+            let value = self.values.pop().ok_or(Trap::StackUnderflow)?;
+            *self.values.get_mut_at(index).ok_or(Trap::BadLocal)? = value;
+        } else if opcode == instructions::LEAVE {
+            // Значения над кадром (результаты функции) остаются на стеке
+            if !self.values.remove_at(self.locals.base, self.locals.length) {
+                return Err(Trap::BadLocal);
+            }
+            self.locals = Locals::default();
+        } else if opcode == instructions::MLOAD8 {
+            let address = self.values.get_number(0)?;
+            let address = self.access(address, 1)?;
+            let value = self.memory.read(address, 1).and_then(get_u8).ok_or(Trap::MemoryLimit)?;
+            self.values.pop();
+            self.values.push(Value::Number(value as u64));
+        } else if opcode == instructions::MLOAD64 {
+            let address = self.values.get_number(0)?;
+            let address = self.access(address, size_of::<u64>() as u64)?;
+            let value = self.memory.read(address, size_of::<u64>()).and_then(get_u64).ok_or(Trap::MemoryLimit)?;
+            self.values.pop();
+            self.values.push(Value::Number(value));
+        } else if opcode == instructions::MLOADB {
+            let address = self.values.get_number(1)?;
+            let length = self.values.get_number(0)?;
+            let address = self.access(address, length)?;
+            let block = self.memory.read(address, length as usize).map(Block::from).ok_or(Trap::MemoryLimit)?;
+            self.values.pop_pair();
+            self.values.push(Value::Block(block));
+        } else if opcode == instructions::MSTORE8 {
+            let address = self.values.get_number(1)?;
+            let value = self.values.get_number(0)?;
+            let address = self.access(address, 1)?;
+            self.memory.write(address, &[value as u8]).ok_or(Trap::MemoryLimit)?;
+            self.values.pop_pair();
+        } else if opcode == instructions::MSTORE64 {
+            let address = self.values.get_number(1)?;
+            let value = self.values.get_number(0)?;
+            let address = self.access(address, size_of::<u64>() as u64)?;
+            self.memory.write(address, &value.to_be_bytes()).ok_or(Trap::MemoryLimit)?;
+            self.values.pop_pair();
+        } else if opcode == instructions::MSTOREB {
+            let address = self.values.get_number(1)?;
+            let block = self.values.get_block(0)?;
+            let address = self.access(address, block.len() as u64)?;
+            self.memory.write(address, block.bytes()).ok_or(Trap::MemoryLimit)?;
+            self.values.pop_pair();
+        } else if opcode == instructions::MSIZE {
+            self.values.push(Value::Number(self.memory.len() as u64));
+        } else if opcode == instructions::SLOAD {
+            // Отсутствующий ключ читается как пустой блок, отличить их можно через SHAS
+            let key = self.values.get_block(0)?;
+            let value = self.load_storage(key).unwrap_or(Block::empty());
+            self.charge_copy(value.len(), value.len())?;
+            self.values.pop();
+            self.values.push(Value::Block(value));
+        } else if opcode == instructions::SSTORE {
+            let key = self.values.get_block(1)?;
+            let value = self.values.get_block(0)?;
+            self.store(key.unpack(), Some(value));
+            self.values.pop_pair();
+        } else if opcode == instructions::SDEL {
+            let key = self.values.get_block(0)?;
+            self.store(key.unpack(), None);
+            self.values.pop();
+        } else if opcode == instructions::SHAS {
+            let key = self.values.get_block(0)?;
+            let value = cond_sign(self.load_storage(key).is_some());
+            self.values.pop();
+            self.values.push(Value::Number(value));
+        } else if opcode == instructions::TUPLE {
+            let length = self.next_u8()? as usize;
+            self.values.require(length)?;
+            let (mut size, mut depth) = (VALUE_HEADER, 1);
+            for offset in 0..length {
+                let (value_size, value_depth) = self.values.get_value(offset)?.measure();
+                size += value_size;
+                depth = depth.max(value_depth + 1);
+            }
+            self.check_value(size, depth)?;
+            let mut values: Vec<Value> = (0..length).map(|_| self.values.pop().unwrap()).collect();
+            values.reverse();
+            self.values.push(Value::Tuple(values));
+        } else if opcode == instructions::UNTUPLE {
+            self.values.get_mut_tuple(0)?;
+            if let Some(Value::Tuple(values)) = self.values.pop() {
+                for value in values {
+                    self.values.push(value);
+                }
+            }
+        } else if opcode == instructions::TGET {
+            let index = self.values.get_number(0)?;
+            let cost = self.values.get_mut_tuple(1)?.get(index as usize).ok_or(Trap::NotFound)?.copy_cost();
+            self.charge(cost)?;
+            let value = self.values.get_mut_tuple(1)?.get(index as usize).ok_or(Trap::NotFound)?.clone();
+            self.values.pop();
+            self.values.push(value);
+        } else if opcode == instructions::TSET {
+            let index = self.values.get_number(1)? as usize;
+            let (value_size, value_depth) = self.values.get_value(0)?.measure();
+            let (tuple_size, tuple_depth) = self.values.get_value(2)?.measure();
+            let old_size = self.values.get_mut_tuple(2)?.get(index).ok_or(Trap::NotFound)?.measure().0;
+            self.check_value(tuple_size - old_size + value_size, tuple_depth.max(value_depth + 1))?;
+            let (_, value) = self.values.pop_pair().ok_or(Trap::StackUnderflow)?;
+            self.values.get_mut_tuple(0)?[index] = value;
+        } else if opcode == instructions::TLEN {
+            let length = self.values.get_mut_tuple(0)?.len();
+            self.values.push(Value::Number(length as u64));
+        } else if opcode == instructions::MKMAP {
+            self.values.push(Value::Map(BTreeMap::new()));
+        } else if opcode == instructions::MAPGET {
+            let key = self.values.get_block(0)?;
+            let cost = self.values.get_mut_map(1)?.get(key.bytes()).ok_or(Trap::NotFound)?.copy_cost();
+            self.charge(cost)?;
+            let value = self.values.get_mut_map(1)?.get(key.bytes()).ok_or(Trap::NotFound)?.clone();
+            self.values.pop();
+            self.values.push(value);
+        } else if opcode == instructions::MAPSET {
+            let key = self.values.get_block(1)?;
+            let (value_size, value_depth) = self.values.get_value(0)?.measure();
+            let (map_size, map_depth) = self.values.get_value(2)?.measure();
+            let old_size = match self.values.get_mut_map(2)?.get(key.bytes()) {
+                Some(old) => size_of::<u64>() + key.len() + old.measure().0,
+                None => 0,
+            };
+            self.check_value(map_size - old_size + size_of::<u64>() + key.len() + value_size, map_depth.max(value_depth + 1))?;
+            let (_, value) = self.values.pop_pair().ok_or(Trap::StackUnderflow)?;
+            self.values.get_mut_map(0)?.insert(key.unpack(), value);
+        } else if opcode == instructions::MAPDEL {
+            let key = self.values.get_block(0)?;
+            self.values.get_mut_map(1)?.remove(key.bytes());
+            self.values.pop();
+        } else if opcode == instructions::MAPHAS {
+            let key = self.values.get_block(0)?;
+            let value = cond_sign(self.values.get_mut_map(1)?.contains_key(key.bytes()));
+            self.values.pop();
+            self.values.push(Value::Number(value));
+        } else if opcode == instructions::MAPLEN {
+            let length = self.values.get_mut_map(0)?.len();
+            self.values.push(Value::Number(length as u64));
+        } else if opcode == instructions::MAPAT {
+            // Доступ по номеру проходит index записей, поэтому газ растёт вместе с ним
+            let index = self.values.get_number(0)?;
+            if index >= self.values.get_mut_map(1)?.len() as u64 {
+                return Err(Trap::NotFound);
+            }
+            self.charge(index.saturating_mul(gas::MAP_STEP_COST))?;
+            let (key, value) = self.values.get_mut_map(1)?.iter().nth(index as usize).ok_or(Trap::NotFound)?;
+            let (key, value) = (Block::new(key), value.clone());
+            self.charge(gas::get_copy_cost(key.len()) + value.copy_cost())?;
+            self.values.pop();
+            self.values.push(Value::Block(key));
+            self.values.push(value);
+        } else if opcode == instructions::VTOB {
+            let cost = gas::get_copy_cost(self.values.get_value(0)?.measure().0);
+            self.charge(cost)?;
+            let block = self.values.get_value(0)?.get_as_block();
+            self.values.pop();
+            self.values.push(Value::Block(block));
+        } else if opcode == instructions::BTOV {
+            let block = self.values.get_block(0)?;
+            if block.len() > MAX_BLOCK_SIZE {
+                return Err(Trap::ValueLimit);
             }
+            self.charge(gas::get_copy_cost(block.len()))?;
+            let value = Value::from_block(block).ok_or(Trap::BadEncoding)?;
+            self.values.pop();
+            self.values.push(value);
+        } else if opcode == instructions::SKIP {
+            let length = self.values.get_number(0)?;
+            self.values.get_mut_slice(1)?.skip(length as usize).ok_or(Trap::SliceUnderflow)?;
+            self.values.pop();
+        } else if opcode == instructions::IREAD16 {
+            let value = self.values.get_mut_slice(0)?.read_u16().ok_or(Trap::SliceUnderflow)?;
+            self.values.push(Value::Number(value as u64));
+        } else if opcode == instructions::IREAD32 {
+            let value = self.values.get_mut_slice(0)?.read_u32().ok_or(Trap::SliceUnderflow)?;
+            self.values.push(Value::Number(value as u64));
+        } else if opcode == instructions::PEEK8 {
+            let value = self.values.get_mut_slice(0)?.peek_u8().ok_or(Trap::SliceUnderflow)?;
+            self.values.push(Value::Number(value as u64));
+        } else if opcode == instructions::PEEK16 {
+            let value = self.values.get_mut_slice(0)?.peek_u16().ok_or(Trap::SliceUnderflow)?;
+            self.values.push(Value::Number(value as u64));
+        } else if opcode == instructions::PEEK32 {
+            let value = self.values.get_mut_slice(0)?.peek_u32().ok_or(Trap::SliceUnderflow)?;
+            self.values.push(Value::Number(value as u64));
+        } else if opcode == instructions::PEEK64 {
+            let value = self.values.get_mut_slice(0)?.peek_u64().ok_or(Trap::SliceUnderflow)?;
+            self.values.push(Value::Number(value));
+        } else if opcode == instructions::BPEEK {
+            let length = self.values.get_number(0)?;
+            let block = self.values.get_mut_slice(1)?.peek_block(length as usize).ok_or(Trap::SliceUnderflow)?;
+            self.values.pop();
+            self.values.push(Value::Block(block));
+        } else if opcode == instructions::BREST {
+            let block = self.values.get_mut_slice(0)?.read_rest();
+            self.values.push(Value::Block(block));
+        } else if opcode == instructions::BREADL {
+            let block = self.values.get_mut_slice(0)?.read_block_with_len().ok_or(Trap::SliceUnderflow)?;
+            self.values.push(Value::Block(block));
+        } else if opcode == instructions::ENDS {
+            if self.values.get_mut_slice(0)?.len() > 0 {
+                return Err(Trap::SliceNotEmpty);
+            }
+            self.values.pop();
+        } else {
+            return Err(Trap::UnknownOpcode);
+        }
+        Ok(())
+    }
+
+    fn load_storage(&self, key: Block) -> Option<Block> {
+        match self.storage.get(key.bytes()) {
+            Some(value) => value.clone(),
+            None => self.host.load_storage(key),
+        }
+    }
+
+    fn store(&mut self, key: Vec<u8>, value: Option<Block>) {
+        if self.catches > 0 {
+            let previous = self.storage.insert(key.clone(), value);
+            self.journal.push(Change::Storage(key, previous));
+        } else {
+            self.storage.insert(key, value);
+        }
+    }
+
+    fn record(&mut self, change: Change) {
+        if self.catches > 0 {
+            self.journal.push(change);
+        }
+    }
+
+    // Возвращает данные и хранилище к состоянию, когда в журнале было length записей
+    fn rollback(&mut self, length: usize) {
+        while self.journal.len() > length {
+            match self.journal.pop() {
+                Some(Change::Data(data)) => self.data = data,
+                Some(Change::Storage(key, Some(value))) => {
+                    self.storage.insert(key, value);
+                },
+                Some(Change::Storage(key, None)) => {
+                    self.storage.remove(&key);
+                },
+                None => break,
+            }
+        }
+    }
+
+    // Вне TRY откатывать нечего, журнал больше не нужен
+    fn leave_try(&mut self) {
+        self.catches -= 1;
+        if self.catches == 0 {
+            self.journal.clear();
+        }
+    }
+
+    // Расширяет память под диапазон и берёт газ за новые страницы
+    fn access(&mut self, address: u64, length: u64) -> Result<usize, Trap> {
+        let pages = self.memory.required_pages(address, length).ok_or(Trap::MemoryLimit)?;
+        self.charge((pages - self.memory.pages()) as u64 * gas::MEMORY_PAGE_COST)?;
+        self.memory.grow(pages);
+        Ok(address as usize)
+    }
+
+    fn charge(&mut self, cost: u64) -> Result<(), Trap> {
+        if self.gas_used.saturating_add(cost) > self.gas_limit {
+            return Err(Trap::OutOfGas);
+        }
+        self.gas_used += cost;
+        Ok(())
+    }
+
+    // Газ за копирование copied байт в блок, который после этого будет длиной length
+    fn charge_copy(&mut self, copied: usize, length: usize) -> Result<(), Trap> {
+        if length > MAX_BLOCK_SIZE {
+            return Err(Trap::SizeLimit);
         }
+        self.charge(gas::get_copy_cost(copied))
     }
 
-    pub fn run(&mut self) {
+    // Копия значения с глубины offset, газ берётся за её размер
+    fn copy_value(&mut self, offset: usize) -> Result<Value, Trap> {
+        let cost = self.values.get_value(offset)?.copy_cost();
+        self.charge(cost)?;
+        Ok(self.values.get_value(offset)?.clone())
+    }
+
+    // Составное значение не больше блока и не глубже MAX_VALUE_DEPTH, газ берётся за его размер
+    fn check_value(&mut self, size: usize, depth: usize) -> Result<(), Trap> {
+        if size > MAX_BLOCK_SIZE || depth > MAX_VALUE_DEPTH {
+            return Err(Trap::ValueLimit);
+        }
+        self.charge(gas::get_copy_cost(size))
+    }
+
+    fn local_index(&self, index: u16) -> Result<usize, Trap> {
+        if index as usize >= self.locals.length {
+            return Err(Trap::BadLocal);
+        }
+        Ok(self.locals.base + index as usize)
+    }
+
+    // Если оба числа u64, считаем в u64, иначе оба приводятся к U256
+    fn arithmetic<F, W>(&mut self, narrow: F, wide: W) -> Result<(), Trap>
+        where F: Fn(u64, u64) -> Result<u64, Trap>, W: Fn(U256, U256) -> Result<U256, Trap> {
+        let value = if self.values.is_narrow_pair() {
+            Value::Number(try_operate(self.values.pair(), narrow)?)
+        } else {
+            Value::Wide(operate_wide(self.values.pair(), wide)?)
+        };
+        self.values.pop_pair();
+        self.values.push(value);
+        Ok(())
+    }
+
+    fn compare<F>(&mut self, f: F) -> Result<(), Trap>
+        where F: Fn(Ordering) -> bool {
+        let value = if self.values.is_narrow_pair() {
+            operate(self.values.pair(), |a, b| cond_sign(f(a.cmp(&b))))?
+        } else {
+            operate_wide(self.values.pair(), |a, b| Ok(cond_sign(f(a.cmp(&b)))))?
+        };
+        self.values.pop_pair();
+        self.values.push(Value::Number(value));
+        Ok(())
+    }
+
+    // Раскручивает стек вызовов до ближайшего TRY и откатывает всё, что сделано после него:
+    // стек значений, данные, хранилище и отправленные сообщения. Обработчик получает код ошибки и признак THROW
+    fn catch(&mut self, trap: Trap) -> bool {
+        if !trap.is_catchable() {
+            return false;
+        }
+        while let Some(frame) = self.calls.pop() {
+            if let Frame::Catch { handler, depth, locals, journal, sent } = frame {
+                self.values.drop(self.values.len().saturating_sub(depth));
+                self.locals = locals;
+                self.rollback(journal);
+                self.host.cancel_messages(sent);
+                self.leave_try();
+                self.values.push(Value::Number(trap.code()));
+                self.values.push(Value::Number(cond_sign(trap.is_thrown())));
+                self.pc = handler;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn step(&mut self) -> Result<(), Trap> {
+        let opcode = self.next_u8()?;
+        self.charge(gas::get_cost(opcode))?;
+        self.execute(opcode)
+    }
+
+    pub fn run(&mut self) -> ExitCode {
         self.stopped = false;
-        while let Some(opcode) = self.next_u8() {
-            if self.stopped {
+        let mut exit_code = ExitCode::Success;
+        while !self.stopped && self.pc < self.code.len() {
+            if let Err(trap) = self.step() {
+                if self.catch(trap) {
+                    continue;
+                }
+                exit_code = ExitCode::Trap(trap);
                 break;
             }
-            self.execute(opcode);
-        }
-        println!("VM STACK:");
-        for message in self.stack() {
-            println!("  {}", message.to_string());
         }
+        self.stopped = true;
+        exit_code
     }
 
     pub fn stack(&self) -> Vec<Value> {
@@ -462,4 +1205,12 @@ impl<'a> VM<'a> {
     pub fn get_data(&self) -> Block {
         self.data.clone()
     }
+
+    pub fn get_storage_changes(&self) -> Vec<(Block, Option<Block>)> {
+        self.storage.iter().map(|(key, value)| (Block::new(key), value.clone())).collect()
+    }
+
+    pub fn get_gas_used(&self) -> u64 {
+        self.gas_used
+    }
 }
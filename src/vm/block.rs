@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
+// Больше VM собрать не даст: BCAT и запись в builder упираются в этот размер
+pub const MAX_BLOCK_SIZE: usize = 64 * 1024;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Block {
     buffer: Vec<u8>
@@ -31,6 +34,20 @@ impl Block {
         Some(Self::new(&hex::decode(value).ok()?))
     }
 
+    pub fn bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn concat(&self, other: &Block) -> Block {
+        let mut buffer = self.buffer.clone();
+        buffer.extend_from_slice(&other.buffer);
+        Self { buffer }
+    }
+
+    pub fn sub(&self, offset: usize, length: usize) -> Option<Block> {
+        Some(Self::new(self.buffer.get(offset..offset.checked_add(length)?)?))
+    }
+
     pub fn hash(&self) -> Block {
         let mut hasher = Sha256::new();
         hasher.update(&self.buffer);
@@ -1,10 +1,18 @@
-use super::block::Block;
+use super::{block::Block, uint::U256};
 
 #[derive(Clone)]
 pub struct Builder {
     bytes: Vec<u8>,
 }
 
+impl From<Block> for Builder {
+    fn from(value: Block) -> Self {
+        Self {
+            bytes: value.unpack(),
+        }
+    }
+}
+
 impl Builder {
     pub fn new() -> Self {
         Self {
@@ -20,6 +28,10 @@ impl Builder {
         self.bytes.extend(value.to_be_bytes());
     }
 
+    pub fn write_u256(&mut self, value: U256) {
+        self.bytes.extend(value.to_be_bytes());
+    }
+
     pub fn write_block(&mut self, value: Block) {
         self.bytes.extend(value.unpack());
     }
@@ -33,6 +45,10 @@ impl Builder {
         self.bytes.len()
     }
 
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
     pub fn build(&self) -> Block {
         Block::new(&self.bytes)
     }
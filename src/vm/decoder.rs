@@ -0,0 +1,85 @@
+use super::{block::Block, instructions::{get_instruction, Instruction, Operand}, uint::U256, utils::{get_relative_reference, get_u16, get_u64, get_u8, Direction}};
+
+#[derive(Clone)]
+pub enum Immediate {
+    U8(u8),
+    U16(u16),
+    U64(u64),
+    U256(U256),
+    Block(Block),
+    Address(usize),
+    Relative(Direction, u16),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum DecodeError {
+    UnknownOpcode(u8),
+    TruncatedOperand,
+}
+
+// Одна разобранная инструкция: offset - где начинается, next - где начинается следующая
+#[derive(Clone)]
+pub struct Decoded {
+    pub offset: usize,
+    pub next: usize,
+    pub instruction: Instruction,
+    pub immediates: Vec<Immediate>,
+}
+
+impl Decoded {
+    // Абсолютные адреса, на которые может перейти инструкция. None - относительный переход за пределы кода
+    pub fn targets(&self) -> Vec<Option<usize>> {
+        self.immediates.iter().filter_map(|immediate| match immediate {
+            Immediate::Address(target) => Some(Some(*target)),
+            Immediate::Relative(Direction::Forward, offset) => Some(self.next.checked_add(*offset as usize)),
+            Immediate::Relative(Direction::Backward, offset) => Some(self.next.checked_sub(*offset as usize)),
+            _ => None,
+        }).collect()
+    }
+}
+
+fn take<'a>(code: &'a [u8], pointer: &mut usize, length: usize) -> Result<&'a [u8], DecodeError> {
+    let end = pointer.checked_add(length).ok_or(DecodeError::TruncatedOperand)?;
+    let bytes = code.get(*pointer..end).ok_or(DecodeError::TruncatedOperand)?;
+    *pointer = end;
+    Ok(bytes)
+}
+
+pub fn decode(code: &[u8], offset: usize) -> Result<Decoded, DecodeError> {
+    let mut pointer = offset;
+    let opcode = get_u8(take(code, &mut pointer, 1)?).ok_or(DecodeError::TruncatedOperand)?;
+    let instruction = get_instruction(opcode).ok_or(DecodeError::UnknownOpcode(opcode))?;
+    let mut immediates = Vec::new();
+    for operand in instruction.operands {
+        let bytes = take(code, &mut pointer, operand.size())?;
+        let immediate = match operand {
+            Operand::U8 => Immediate::U8(bytes[0]),
+            Operand::U16 => Immediate::U16(get_u16(bytes).unwrap()),
+            Operand::U64 => Immediate::U64(get_u64(bytes).unwrap()),
+            Operand::U256 => Immediate::U256(U256::from_be_slice(bytes).unwrap()),
+            Operand::Block => {
+                let length = get_u64(bytes).unwrap() as usize;
+                Immediate::Block(Block::from(take(code, &mut pointer, length)?))
+            },
+            Operand::Address => Immediate::Address(get_u64(bytes).unwrap() as usize),
+            Operand::Relative => {
+                let (direction, offset) = get_relative_reference(get_u16(bytes).unwrap());
+                Immediate::Relative(direction, offset)
+            },
+        };
+        immediates.push(immediate);
+    }
+    Ok(Decoded { offset, next: pointer, instruction, immediates })
+}
+
+// Разбирает весь код подряд, начиная с нуля
+pub fn decode_all(code: &[u8]) -> Result<Vec<Decoded>, (usize, DecodeError)> {
+    let mut decoded = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let instruction = decode(code, offset).map_err(|error| (offset, error))?;
+        offset = instruction.next;
+        decoded.push(instruction);
+    }
+    Ok(decoded)
+}
@@ -1,36 +1,97 @@
 use std::{cell::RefCell, rc::Rc};
 
-use crate::program::ProgramReaderFromBytes;
+use crate::{program::ProgramReaderFromBytes, verifier::verify_bytes};
 
-use super::{block::{AsBlock, Block}, builder::Builder, message::{Init, Message}, SendMessage, Value, VM};
+use super::{block::{AsBlock, Block}, builder::Builder, gas, message::{Init, Message}, overlay::Overlay, trap::{ExitCode, Trap}, LoadStorage, SendMessage, Value, VM};
 
 pub trait Repository {
     fn get_contract_program(&self, address: Block) -> Option<Block>;
     fn get_contract_data(&self, address: Block) -> Option<Block>;
+    fn get_contract_storage(&self, address: Block, key: Block) -> Option<Block>;
     fn save_transaction(&mut self, transaction: TransactionPart);
+    // Итоговое состояние контракта после транзакции, program есть только у развёрнутых в ней
+    fn save_contract(&mut self, address: Block, program: Option<Block>, data: Block);
     fn get_all_messages(&self, limit: u64, offset: u64) -> Vec<Message>;
     fn get_messages_by_contract(&self, address: Block, limit: u64, offset: u64) -> Vec<Message>;
 }
 
+// Ограничения на каскад сообщений в одной транзакции
+#[derive(Clone, Copy)]
+pub struct Limits {
+    // Сколько уровней сообщений допустимо, считая исходное внешнее
+    pub max_depth: usize,
+    pub max_messages: usize,
+    pub max_sends: usize,
+    // Верхняя граница газа на транзакцию или view, что бы ни запросил клиент
+    pub max_gas: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_depth: 16,
+            max_messages: 256,
+            max_sends: 64,
+            max_gas: gas::DEFAULT_GAS_LIMIT,
+        }
+    }
+}
+
+// Общее состояние для всех сообщений одной транзакции
+struct Transaction {
+    overlay: Rc<RefCell<Overlay>>,
+    limits: Limits,
+    gas: u64,
+    messages: usize,
+}
+
 #[derive(Clone)]
 pub struct Environment {
     message: Message,
     order: Vec<Message>,
-    repository: Rc<RefCell<dyn Repository>>
+    max_sends: usize,
+    gas_limit: u64,
+    gas_used: u64,
+    exit_code: ExitCode,
+    storage: Vec<(Block, Option<Block>)>,
+    overlay: Rc<RefCell<Overlay>>,
 }
 
 #[derive(Clone)]
 pub struct ContractState {
     pub message: Message,
     pub data: Block,
+    // Только изменённые ключи хранилища, None - ключ удалён
+    pub storage: Vec<(Block, Option<Block>)>,
+    pub gas_used: u64,
+    pub exit_code: ExitCode,
     pub children: Vec<TransactionPart>,
 }
 
+impl ContractState {
+    pub fn is_committed(&self) -> bool {
+        self.exit_code.is_success()
+    }
+}
+
 impl AsBlock for ContractState {
     fn get_as_block(&self) -> Block {
         let mut builder = Builder::new();
         builder.write_block_with_len(self.message.get_as_block());
         builder.write_block_with_len(self.data.clone());
+        builder.write_u64(self.storage.len() as u64);
+        for (key, value) in &self.storage {
+            builder.write_block_with_len(key.clone());
+            match value {
+                Some(value) => {
+                    builder.write_u8(1);
+                    builder.write_block_with_len(value.clone());
+                },
+                None => builder.write_u8(0),
+            }
+        }
+        builder.write_u64(self.gas_used);
+        builder.write_block(self.exit_code.get_as_block());
         builder.write_u64(self.children.len() as u64);
         for child in self.children.clone() {
             builder.write_block_with_len(child.get_as_block());
@@ -65,11 +126,16 @@ impl AsBlock for TransactionPart {
 }
 
 impl Environment {
-    fn new(message: Message, repository: Rc<RefCell<dyn Repository>>) -> Self {
+    fn new(message: Message, gas_limit: u64, max_sends: usize, overlay: Rc<RefCell<Overlay>>) -> Self {
         Self {
             message,
             order: Vec::new(),
-            repository: repository,
+            max_sends,
+            gas_limit,
+            gas_used: 0,
+            exit_code: ExitCode::Success,
+            storage: Vec::new(),
+            overlay,
         }
     }
 
@@ -81,58 +147,196 @@ impl Environment {
                 return None;
             }
         } else {
-            let repository = self.repository.borrow();
+            let overlay = self.overlay.borrow();
             let address = self.message.receiver.clone();
-            init = Some(Init { program: repository.get_contract_program(address.clone())?, data: repository.get_contract_data(address.clone())? });
+            init = Some(Init { program: overlay.get_contract_program(address.clone())?, data: overlay.get_contract_data(address.clone())? });
         }
         if let Some(init) = init {
-            let program = ProgramReaderFromBytes::new(&init.clone().program.unpack()).load()?;
-            let entrypoint = program.get_entrypoint(self.message.message_type)?;
-            return Some(VM::new(program.get_code(), entrypoint, init.data, self.message.clone(), self))
+            let program = ProgramReaderFromBytes::new(&init.clone().program.unpack()).load().ok()?;
+            let entrypoint = program.get_entrypoint(self.message.message_type, self.message.opcode)?;
+            let gas_limit = self.gas_limit;
+            let constants = Block::from(program.get_constants());
+            return Some(VM::new(program.get_code(), entrypoint, constants, init.data, self.message.clone(), gas_limit, self))
         }
         None
     }
 
+    // При ошибке изменения откатываются: возвращаются исходные данные, а отправленные сообщения отбрасываются
     fn run(&mut self) -> Option<Block> {
         let mut vm = self.get_vm()?;
-        vm.run();
-        Some(vm.get_data())
+        let initial_data = vm.get_data();
+        let exit_code = vm.run();
+        let gas_used = vm.get_gas_used();
+        let data = vm.get_data();
+        let storage = vm.get_storage_changes();
+        self.gas_used = gas_used;
+        self.exit_code = exit_code;
+        if exit_code.is_success() {
+            self.storage = storage;
+            Some(data)
+        } else {
+            self.order.clear();
+            Some(initial_data)
+        }
     }
 
-    fn run_view(&mut self) -> Option<Vec<Value>> {
+    fn run_view(&mut self) -> Option<(ExitCode, Vec<Value>)> {
         let mut vm = self.get_vm()?;
-        vm.run();
-        Some(vm.stack())
+        let exit_code = vm.run();
+        Some((exit_code, vm.stack()))
     }
 
-    fn execute(message: Message, repository: Rc<RefCell<dyn Repository>>) -> TransactionPart {
-        let mut env = Self::new(message.clone(), repository.clone());
+    fn reject(message: Message, trap: Trap) -> TransactionPart {
+        TransactionPart::State(ContractState {
+            message,
+            data: Block::empty(),
+            storage: Vec::new(),
+            gas_used: 0,
+            exit_code: ExitCode::Trap(trap),
+            children: Vec::new(),
+        })
+    }
+
+    fn execute(message: Message, transaction: &mut Transaction, depth: usize) -> TransactionPart {
+        if depth >= transaction.limits.max_depth {
+            return Self::reject(message, Trap::DepthLimit);
+        }
+        if transaction.messages >= transaction.limits.max_messages {
+            return Self::reject(message, Trap::MessageLimit);
+        }
+        transaction.messages += 1;
+        if let Some(init) = message.init.clone() {
+            if verify_bytes(&init.program.unpack()).is_err() {
+                return Self::reject(message, Trap::BadProgram);
+            }
+        }
+        let overlay = transaction.overlay.clone();
+        let mut env = Self::new(message.clone(), message.gas_limit.min(transaction.gas), transaction.limits.max_sends, overlay.clone());
         let data = env.run();
+        transaction.gas -= env.gas_used;
         if let Some(data) = data {
+            if env.exit_code.is_success() {
+                let mut overlay = overlay.borrow_mut();
+                if let Some(init) = message.init.clone() {
+                    overlay.set_contract_program(message.receiver.clone(), init.program);
+                }
+                overlay.set_contract_data(message.receiver.clone(), data.clone());
+                for (key, value) in env.storage.clone() {
+                    overlay.set_contract_storage(message.receiver.clone(), key, value);
+                }
+            }
             return TransactionPart::State(ContractState { 
                 message,
                 data: data,
-                children: env.order.iter().map(|x| Self::execute(x.clone(), repository.clone())).collect()
+                storage: env.storage.clone(),
+                gas_used: env.gas_used,
+                exit_code: env.exit_code,
+                children: env.order.iter().map(|x| Self::execute(x.clone(), transaction, depth + 1)).collect()
             });
         } else {
             return TransactionPart::Message(message);
         }
     }
 
-    pub fn view(message: Message, repository: Rc<RefCell<dyn Repository>>) -> Vec<Value> {
-        let mut env = Self::new(message.clone(), repository.clone());
-        env.run_view().or(Some(Vec::new())).unwrap()
+    // Без контракта или точки входа под опкод view завершается с NotFound и пустым стеком
+    pub fn view(message: Message, repository: Rc<RefCell<dyn Repository>>, limits: Limits) -> (ExitCode, Vec<Value>) {
+        let overlay = Rc::new(RefCell::new(Overlay::new(repository)));
+        let mut env = Self::new(message.clone(), message.gas_limit.min(limits.max_gas), 0, overlay);
+        env.run_view().unwrap_or((ExitCode::Trap(Trap::NotFound), Vec::new()))
     }
 
-    pub fn start_transaction(message: Message, repository: Rc<RefCell<dyn Repository>>) -> TransactionPart {
-        let transaction = Self::execute(message, repository.clone());
-        repository.borrow_mut().save_transaction(transaction.clone());
-        transaction
+    pub fn start_transaction(message: Message, repository: Rc<RefCell<dyn Repository>>, limits: Limits) -> TransactionPart {
+        let mut transaction = Transaction {
+            overlay: Rc::new(RefCell::new(Overlay::new(repository))),
+            limits,
+            gas: message.gas_limit.min(limits.max_gas),
+            messages: 0,
+        };
+        let result = Self::execute(message, &mut transaction, 0);
+        transaction.overlay.borrow_mut().flush(result.clone());
+        result
+    }
+}
+
+impl LoadStorage for Environment {
+    fn load_storage(&self, key: Block) -> Option<Block> {
+        self.overlay.borrow().get_contract_storage(self.message.receiver.clone(), key)
     }
 }
 
 impl SendMessage for Environment {
-    fn send_message(&mut self, message: Message) {
+    fn send_message(&mut self, message: Message) -> Result<(), Trap> {
+        if self.order.len() >= self.max_sends {
+            return Err(Trap::SendLimit);
+        }
         self.order.push(message);
+        Ok(())
+    }
+
+    fn sent_messages(&self) -> usize {
+        self.order.len()
+    }
+
+    fn cancel_messages(&mut self, count: usize) {
+        self.order.truncate(count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{program::{Entrypoint, Program}, vm::{block::{AsBlock, Block}, message::{Init, Message, MessageType}, trap::{ExitCode, Trap}}};
+
+    use super::{Environment, Limits, Repository, TransactionPart};
+
+    // Пустой репозиторий, запоминает адреса сохранённых контрактов
+    #[derive(Default)]
+    struct Contracts {
+        saved: Vec<Block>,
+    }
+
+    impl Repository for Contracts {
+        fn get_contract_program(&self, _address: Block) -> Option<Block> {
+            None
+        }
+
+        fn get_contract_data(&self, _address: Block) -> Option<Block> {
+            None
+        }
+
+        fn get_contract_storage(&self, _address: Block, _key: Block) -> Option<Block> {
+            None
+        }
+
+        fn save_transaction(&mut self, _transaction: TransactionPart) {}
+
+        fn save_contract(&mut self, address: Block, _program: Option<Block>, _data: Block) {
+            self.saved.push(address);
+        }
+
+        fn get_all_messages(&self, _limit: u64, _offset: u64) -> Vec<Message> {
+            Vec::new()
+        }
+
+        fn get_messages_by_contract(&self, _address: Block, _limit: u64, _offset: u64) -> Vec<Message> {
+            Vec::new()
+        }
+    }
+
+    #[test]
+    fn rejects_unverified_init() {
+        // Контейнер без ошибок, но код - неизвестный опкод
+        let program = Program::new(vec![0xff], Entrypoint::none(), Entrypoint::none(), Entrypoint::none(), Vec::new(), Vec::new());
+        let init = Init { program: Block::from(program.to_bytes()), data: Block::empty() };
+        let address = init.get_as_block().hash();
+        let message = Message::new(MessageType::External, Block::empty(), 0, Block::empty(), address, Some(init), 1000);
+        let repository = Rc::new(RefCell::new(Contracts::default()));
+        let result = Environment::start_transaction(message, repository.clone(), Limits::default());
+        match result {
+            TransactionPart::State(state) => assert_eq!(state.exit_code, ExitCode::Trap(Trap::BadProgram)),
+            TransactionPart::Message(_) => panic!("message was not rejected"),
+        }
+        assert!(repository.borrow().saved.is_empty());
     }
 }
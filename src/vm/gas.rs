@@ -0,0 +1,40 @@
+use super::instructions;
+
+pub const BASE_COST: u64 = 1;
+pub const JUMP_COST: u64 = 2;
+pub const HASH_COST: u64 = 30;
+pub const DATA_COST: u64 = 20;
+pub const STORAGE_WRITE_COST: u64 = 50;
+pub const MESSAGE_COST: u64 = 10;
+pub const SEND_COST: u64 = 100;
+// За каждую новую страницу рабочей памяти, сверх цены инструкции
+pub const MEMORY_PAGE_COST: u64 = 50;
+// За каждые начатые COPY_WORD байт, которые инструкция копирует, сверх цены инструкции
+pub const COPY_WORD_COST: u64 = 1;
+pub const COPY_WORD: usize = 32;
+// За каждую пропущенную запись при обходе словаря по номеру (MAPAT)
+pub const MAP_STEP_COST: u64 = 1;
+
+// Бюджет для сообщений старого формата, в которых нет поля gas_limit
+pub const DEFAULT_GAS_LIMIT: u64 = 1_000_000;
+
+pub fn get_copy_cost(length: usize) -> u64 {
+    length.div_ceil(COPY_WORD) as u64 * COPY_WORD_COST
+}
+
+// Стоимость выполнения одной инструкции
+pub fn get_cost(opcode: u8) -> u64 {
+    match opcode {
+        instructions::JMP | instructions::JMT | instructions::JMF
+        | instructions::RJMP | instructions::RJMT | instructions::RJMF
+        | instructions::CALL | instructions::RET
+        | instructions::TRY | instructions::ENDTRY => JUMP_COST,
+        instructions::BHASH | instructions::MHASH => HASH_COST,
+        instructions::LDATA | instructions::SDATA
+        | instructions::SLOAD | instructions::SHAS => DATA_COST,
+        instructions::SSTORE | instructions::SDEL => STORAGE_WRITE_COST,
+        instructions::MESSAGE => MESSAGE_COST,
+        instructions::SEND => SEND_COST,
+        _ => BASE_COST,
+    }
+}
@@ -51,3 +51,300 @@ pub const LDATA: u8 = HALT + 1;
 pub const SDATA: u8 = LDATA + 1;
 pub const MESSAGE: u8 = SDATA + 1;
 pub const SEND: u8 = MESSAGE + 1;
+
+pub const THROW: u8 = SEND + 1; // THROW #[code]
+pub const THROWIF: u8 = THROW + 1; // THROWIF #[code]
+pub const THROWIFNOT: u8 = THROWIF + 1; // THROWIFNOT #[code]
+pub const TRY: u8 = THROWIFNOT + 1; // TRY &[handler], обработчику: -- code thrown
+pub const ENDTRY: u8 = TRY + 1;
+
+pub const AND: u8 = ENDTRY + 1; // &
+pub const OR: u8 = AND + 1; // |
+pub const XOR: u8 = OR + 1; // ^
+pub const NOT: u8 = XOR + 1; // ~
+pub const SHL: u8 = NOT + 1; // <<
+pub const SHR: u8 = SHL + 1; // >>
+pub const LNOT: u8 = SHR + 1; // !
+
+pub const ADDW: u8 = LNOT + 1; // Wrapping ADD
+pub const SUBW: u8 = ADDW + 1; // Wrapping SUB
+pub const MULW: u8 = SUBW + 1; // Wrapping MUL
+pub const ADDS: u8 = MULW + 1; // Saturating ADD
+pub const SUBS: u8 = ADDS + 1; // Saturating SUB
+pub const MULS: u8 = SUBS + 1; // Saturating MUL
+
+pub const IPUSH256: u8 = MULS + 1; // IPUSH256 [value]
+pub const IREAD256: u8 = IPUSH256 + 1; // U256READ
+pub const IWRITE256: u8 = IREAD256 + 1; // U256WRITE
+pub const WIDEN: u8 = IWRITE256 + 1; // u64 -> u256
+pub const NARROW: u8 = WIDEN + 1; // u256 -> u64
+pub const BTOW: u8 = NARROW + 1; // Block -> u256
+pub const WTOB: u8 = BTOW + 1; // u256 -> Block
+
+pub const SCMB: u8 = WTOB + 1; // signed >
+pub const SCML: u8 = SCMB + 1; // signed <
+pub const SCMBE: u8 = SCML + 1; // signed >=
+pub const SCMLE: u8 = SCMBE + 1; // signed <=
+pub const SDIV: u8 = SCMLE + 1; // signed DIV
+pub const SMOD: u8 = SDIV + 1; // signed MOD
+pub const NEG: u8 = SMOD + 1; // -x
+pub const ABS: u8 = NEG + 1; // |x|
+pub const IREAD8S: u8 = ABS + 1; // I8READ
+pub const IREAD16S: u8 = IREAD8S + 1; // I16READ
+pub const IREAD32S: u8 = IREAD16S + 1; // I32READ
+
+pub const DUP: u8 = IREAD32S + 1; // a -- a a
+pub const OVER: u8 = DUP + 1; // a b -- a b a
+pub const ROT: u8 = OVER + 1; // a b c -- b c a
+pub const NIP: u8 = ROT + 1; // a b -- b
+pub const TUCK: u8 = NIP + 1; // a b -- b a b
+pub const PICK: u8 = TUCK + 1; // ... n -- ... x
+pub const ROLL: u8 = PICK + 1; // ... n -- ... x
+pub const DEPTH: u8 = ROLL + 1; // -- n
+
+pub const BEQ: u8 = DEPTH + 1; // ==
+pub const BCMP: u8 = BEQ + 1; // -1 / 0 / 1
+pub const BCAT: u8 = BCMP + 1; // a b -- ab
+pub const BSUB: u8 = BCAT + 1; // block offset length -- block
+pub const BTON: u8 = BSUB + 1; // Block -> u64
+pub const NTOB: u8 = BTON + 1; // u64 -> Block
+
+pub const MSENDER: u8 = NTOB + 1; // message.sender
+pub const MSELF: u8 = MSENDER + 1; // message.receiver
+pub const MOPCODE: u8 = MSELF + 1; // message.opcode
+pub const MBODY: u8 = MOPCODE + 1; // message.body
+pub const MTIME: u8 = MBODY + 1; // message.timestamp
+pub const MTYPE: u8 = MTIME + 1; // message.message_type
+pub const MHASH: u8 = MTYPE + 1; // hash(message)
+
+pub const LCONST: u8 = MHASH + 1; // program constants section
+
+pub const ENTER: u8 = LCONST + 1; // ENTER #[locals]
+pub const LLOAD: u8 = ENTER + 1; // LLOAD #[index]
+pub const LSTORE: u8 = LLOAD + 1; // LSTORE #[index]
+pub const LEAVE: u8 = LSTORE + 1;
+
+pub const MLOAD8: u8 = LEAVE + 1; // address -- value
+pub const MLOAD64: u8 = MLOAD8 + 1; // address -- value
+pub const MLOADB: u8 = MLOAD64 + 1; // address length -- block
+pub const MSTORE8: u8 = MLOADB + 1; // address value --
+pub const MSTORE64: u8 = MSTORE8 + 1; // address value --
+pub const MSTOREB: u8 = MSTORE64 + 1; // address block --
+pub const MSIZE: u8 = MSTOREB + 1; // -- size
+
+pub const SLOAD: u8 = MSIZE + 1; // key -- value
+pub const SSTORE: u8 = SLOAD + 1; // key value --
+pub const SDEL: u8 = SSTORE + 1; // key --
+pub const SHAS: u8 = SDEL + 1; // key -- flag
+
+pub const TUPLE: u8 = SHAS + 1; // TUPLE [length]: v0 ... vn -- tuple
+pub const UNTUPLE: u8 = TUPLE + 1; // tuple -- v0 ... vn
+pub const TGET: u8 = UNTUPLE + 1; // tuple index -- tuple value
+pub const TSET: u8 = TGET + 1; // tuple index value -- tuple
+pub const TLEN: u8 = TSET + 1; // tuple -- tuple length
+pub const MKMAP: u8 = TLEN + 1; // -- map
+pub const MAPGET: u8 = MKMAP + 1; // map key -- map value
+pub const MAPSET: u8 = MAPGET + 1; // map key value -- map
+pub const MAPDEL: u8 = MAPSET + 1; // map key -- map
+pub const MAPHAS: u8 = MAPDEL + 1; // map key -- map flag
+pub const MAPLEN: u8 = MAPHAS + 1; // map -- map length
+pub const MAPAT: u8 = MAPLEN + 1; // map index -- map key value, ключи по возрастанию
+pub const VTOB: u8 = MAPAT + 1; // value -- block
+pub const BTOV: u8 = VTOB + 1; // block -- value
+
+pub const SKIP: u8 = BTOV + 1; // slice length -- slice
+pub const IREAD16: u8 = SKIP + 1; // U16READ
+pub const IREAD32: u8 = IREAD16 + 1; // U32READ
+pub const PEEK8: u8 = IREAD32 + 1; // slice -- slice value, без сдвига
+pub const PEEK16: u8 = PEEK8 + 1;
+pub const PEEK32: u8 = PEEK16 + 1;
+pub const PEEK64: u8 = PEEK32 + 1;
+pub const BPEEK: u8 = PEEK64 + 1; // slice length -- slice block, без сдвига
+pub const BREST: u8 = BPEEK + 1; // slice -- slice block
+pub const BREADL: u8 = BREST + 1; // slice -- slice block, длина u64 перед блоком
+pub const ENDS: u8 = BREADL + 1; // slice --, ошибка если остались байты
+
+// Непосредственные операнды, которые идут в коде после опкода
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operand {
+    U8, // [value]
+    U16, // #[value]
+    U64, // [value]
+    U256, // [value]
+    Block, // [length], [bytes...]
+    Address, // &[ip]
+    Relative, // Relative &[ip]
+}
+
+impl Operand {
+    // Длина операнда Block зависит от префикса, здесь только его размер
+    pub fn size(&self) -> usize {
+        match self {
+            Operand::U8 => 1,
+            Operand::U16 | Operand::Relative => 2,
+            Operand::U64 | Operand::Address | Operand::Block => 8,
+            Operand::U256 => 32,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Instruction {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+}
+
+pub fn get_instruction(opcode: u8) -> Option<Instruction> {
+    let (mnemonic, operands): (&'static str, &'static [Operand]) = match opcode {
+        IPUSH64 => ("IPUSH64", &[Operand::U64]),
+        IPUSH8 => ("IPUSH8", &[Operand::U8]),
+        SPUSH => ("SPUSH", &[Operand::U16]),
+        BPUSH => ("BPUSH", &[Operand::Block]),
+        DROPN => ("DROPN", &[Operand::U16]),
+        CHG => ("CHG", &[Operand::U16, Operand::U16]),
+        SWAP => ("SWAP", &[]),
+        BHASH => ("BHASH", &[]),
+        BLEN => ("BLEN", &[]),
+        MKSLICE => ("MKSLICE", &[]),
+        IREAD64 => ("IREAD64", &[]),
+        IREAD8 => ("IREAD8", &[]),
+        BREAD => ("BREAD", &[]),
+        SLLEN => ("SLLEN", &[]),
+        MKBUILDER => ("MKBUILDER", &[]),
+        IWRITE64 => ("IWRITE64", &[]),
+        IWRITE8 => ("IWRITE8", &[]),
+        BWRITE => ("BWRITE", &[]),
+        BUILD => ("BUILD", &[]),
+        BLLEN => ("BLLEN", &[]),
+        ADD => ("ADD", &[]),
+        SUB => ("SUB", &[]),
+        MUL => ("MUL", &[]),
+        DIV => ("DIV", &[]),
+        MOD => ("MOD", &[]),
+        INC => ("INC", &[]),
+        CMB => ("CMB", &[]),
+        CML => ("CML", &[]),
+        CMBE => ("CMBE", &[]),
+        CMLE => ("CMLE", &[]),
+        CME => ("CME", &[]),
+        CMNE => ("CMNE", &[]),
+        JMP => ("JMP", &[Operand::Address]),
+        JMT => ("JMT", &[Operand::Address]),
+        JMF => ("JMF", &[Operand::Address]),
+        RJMP => ("RJMP", &[Operand::Relative]),
+        RJMT => ("RJMT", &[Operand::Relative]),
+        RJMF => ("RJMF", &[Operand::Relative]),
+        CALL => ("CALL", &[Operand::Address]),
+        RET => ("RET", &[]),
+        HALT => ("HALT", &[]),
+        LDATA => ("LDATA", &[]),
+        SDATA => ("SDATA", &[]),
+        MESSAGE => ("MESSAGE", &[]),
+        SEND => ("SEND", &[]),
+        THROW => ("THROW", &[Operand::U16]),
+        THROWIF => ("THROWIF", &[Operand::U16]),
+        THROWIFNOT => ("THROWIFNOT", &[Operand::U16]),
+        TRY => ("TRY", &[Operand::Address]),
+        ENDTRY => ("ENDTRY", &[]),
+        AND => ("AND", &[]),
+        OR => ("OR", &[]),
+        XOR => ("XOR", &[]),
+        NOT => ("NOT", &[]),
+        SHL => ("SHL", &[]),
+        SHR => ("SHR", &[]),
+        LNOT => ("LNOT", &[]),
+        ADDW => ("ADDW", &[]),
+        SUBW => ("SUBW", &[]),
+        MULW => ("MULW", &[]),
+        ADDS => ("ADDS", &[]),
+        SUBS => ("SUBS", &[]),
+        MULS => ("MULS", &[]),
+        IPUSH256 => ("IPUSH256", &[Operand::U256]),
+        IREAD256 => ("IREAD256", &[]),
+        IWRITE256 => ("IWRITE256", &[]),
+        WIDEN => ("WIDEN", &[]),
+        NARROW => ("NARROW", &[]),
+        BTOW => ("BTOW", &[]),
+        WTOB => ("WTOB", &[]),
+        SCMB => ("SCMB", &[]),
+        SCML => ("SCML", &[]),
+        SCMBE => ("SCMBE", &[]),
+        SCMLE => ("SCMLE", &[]),
+        SDIV => ("SDIV", &[]),
+        SMOD => ("SMOD", &[]),
+        NEG => ("NEG", &[]),
+        ABS => ("ABS", &[]),
+        IREAD8S => ("IREAD8S", &[]),
+        IREAD16S => ("IREAD16S", &[]),
+        IREAD32S => ("IREAD32S", &[]),
+        DUP => ("DUP", &[]),
+        OVER => ("OVER", &[]),
+        ROT => ("ROT", &[]),
+        NIP => ("NIP", &[]),
+        TUCK => ("TUCK", &[]),
+        PICK => ("PICK", &[]),
+        ROLL => ("ROLL", &[]),
+        DEPTH => ("DEPTH", &[]),
+        BEQ => ("BEQ", &[]),
+        BCMP => ("BCMP", &[]),
+        BCAT => ("BCAT", &[]),
+        BSUB => ("BSUB", &[]),
+        BTON => ("BTON", &[]),
+        NTOB => ("NTOB", &[]),
+        MSENDER => ("MSENDER", &[]),
+        MSELF => ("MSELF", &[]),
+        MOPCODE => ("MOPCODE", &[]),
+        MBODY => ("MBODY", &[]),
+        MTIME => ("MTIME", &[]),
+        MTYPE => ("MTYPE", &[]),
+        MHASH => ("MHASH", &[]),
+        LCONST => ("LCONST", &[]),
+        ENTER => ("ENTER", &[Operand::U16]),
+        LLOAD => ("LLOAD", &[Operand::U16]),
+        LSTORE => ("LSTORE", &[Operand::U16]),
+        LEAVE => ("LEAVE", &[]),
+        MLOAD8 => ("MLOAD8", &[]),
+        MLOAD64 => ("MLOAD64", &[]),
+        MLOADB => ("MLOADB", &[]),
+        MSTORE8 => ("MSTORE8", &[]),
+        MSTORE64 => ("MSTORE64", &[]),
+        MSTOREB => ("MSTOREB", &[]),
+        MSIZE => ("MSIZE", &[]),
+        SLOAD => ("SLOAD", &[]),
+        SSTORE => ("SSTORE", &[]),
+        SDEL => ("SDEL", &[]),
+        SHAS => ("SHAS", &[]),
+        TUPLE => ("TUPLE", &[Operand::U8]),
+        UNTUPLE => ("UNTUPLE", &[]),
+        TGET => ("TGET", &[]),
+        TSET => ("TSET", &[]),
+        TLEN => ("TLEN", &[]),
+        MKMAP => ("MKMAP", &[]),
+        MAPGET => ("MAPGET", &[]),
+        MAPSET => ("MAPSET", &[]),
+        MAPDEL => ("MAPDEL", &[]),
+        MAPHAS => ("MAPHAS", &[]),
+        MAPLEN => ("MAPLEN", &[]),
+        MAPAT => ("MAPAT", &[]),
+        VTOB => ("VTOB", &[]),
+        BTOV => ("BTOV", &[]),
+        SKIP => ("SKIP", &[]),
+        IREAD16 => ("IREAD16", &[]),
+        IREAD32 => ("IREAD32", &[]),
+        PEEK8 => ("PEEK8", &[]),
+        PEEK16 => ("PEEK16", &[]),
+        PEEK32 => ("PEEK32", &[]),
+        PEEK64 => ("PEEK64", &[]),
+        BPEEK => ("BPEEK", &[]),
+        BREST => ("BREST", &[]),
+        BREADL => ("BREADL", &[]),
+        ENDS => ("ENDS", &[]),
+        _ => return None,
+    };
+    Some(Instruction { opcode, mnemonic, operands })
+}
+
+pub fn find_instruction(mnemonic: &str) -> Option<Instruction> {
+    (0..=u8::MAX).filter_map(get_instruction).find(|instruction| instruction.mnemonic == mnemonic)
+}
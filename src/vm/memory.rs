@@ -0,0 +1,57 @@
+pub const PAGE_SIZE: usize = 1024;
+pub const MAX_PAGES: usize = 64;
+
+// Рабочая память на время одного запуска VM, растёт страницами и не сохраняется
+#[derive(Clone, Default)]
+pub struct Memory {
+    bytes: Vec<u8>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn pages(&self) -> usize {
+        self.bytes.len() / PAGE_SIZE
+    }
+
+    // Сколько страниц нужно, чтобы поместился диапазон [offset, offset + length). None - больше MAX_PAGES
+    pub fn required_pages(&self, offset: u64, length: u64) -> Option<usize> {
+        if length == 0 {
+            return Some(self.pages());
+        }
+        let end = offset.checked_add(length)? as usize;
+        let pages = end.div_ceil(PAGE_SIZE);
+        if pages > MAX_PAGES {
+            return None;
+        }
+        Some(pages.max(self.pages()))
+    }
+
+    // Новые страницы заполнены нулями
+    pub fn grow(&mut self, pages: usize) {
+        if pages > self.pages() {
+            self.bytes.resize(pages * PAGE_SIZE, 0);
+        }
+    }
+
+    pub fn read(&self, offset: usize, length: usize) -> Option<&[u8]> {
+        self.bytes.get(offset..offset.checked_add(length)?)
+    }
+
+    pub fn write(&mut self, offset: usize, bytes: &[u8]) -> Option<()> {
+        self.bytes.get_mut(offset..offset.checked_add(bytes.len())?)?.copy_from_slice(bytes);
+        Some(())
+    }
+}
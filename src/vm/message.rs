@@ -2,7 +2,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
 
-use super::{block::{AsBlock, Block}, builder::Builder, slice::Slice};
+use super::{block::{AsBlock, Block, MAX_BLOCK_SIZE}, builder::Builder, gas, slice::Slice};
 
 #[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum MessageType {
@@ -11,6 +11,16 @@ pub enum MessageType {
     View,
 }
 
+impl MessageType {
+    pub fn to_u8(self) -> u8 {
+        match self {
+            MessageType::External => 0,
+            MessageType::Internal => 1,
+            MessageType::View => 2,
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Init {
     pub program: Block,
@@ -44,10 +54,11 @@ pub struct Message {
     pub opcode: u64,
     pub body: Block,
     pub timestamp: u64,
+    pub gas_limit: u64,
 }
 
 impl Message {
-    pub fn new(message_type: MessageType, body: Block, opcode: u64, sender: Block, receiver: Block, init: Option<Init>) -> Self {
+    pub fn new(message_type: MessageType, body: Block, opcode: u64, sender: Block, receiver: Block, init: Option<Init>, gas_limit: u64) -> Self {
         Self {
             message_type,
             body,
@@ -56,6 +67,7 @@ impl Message {
             opcode,
             init,
             timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64,
+            gas_limit,
         }
     }
 
@@ -72,19 +84,21 @@ impl Message {
         let init = Init::from_block(slice.read_block_with_len()?);
         let opcode = slice.read_u64()?;
         let body = slice.read_block_with_len()?;
+        // Тело и данные попадают в VM как блоки, больше MAX_BLOCK_SIZE их не принимаем
+        if body.len() > MAX_BLOCK_SIZE || init.as_ref().is_some_and(|init| init.data.len() > MAX_BLOCK_SIZE) {
+            return None;
+        }
         let timestamp = slice.read_u64()?;
-        Some(Message { message_type, sender, receiver, init, opcode, body, timestamp })
+        // Старые клиенты не передают gas_limit, для них берётся бюджет по умолчанию
+        let gas_limit = if slice.len() == 0 { gas::DEFAULT_GAS_LIMIT } else { slice.read_u64()? };
+        Some(Message { message_type, sender, receiver, init, opcode, body, timestamp, gas_limit })
     }
 }
 
 impl AsBlock for Message {
     fn get_as_block(&self) -> Block {
         let mut builder = Builder::new();
-        builder.write_u8(match self.message_type {
-            MessageType::Internal => 1,
-            MessageType::External => 0,
-            MessageType::View => 2,
-        });
+        builder.write_u8(self.message_type.to_u8());
         builder.write_block_with_len(self.sender.clone());
         builder.write_block_with_len(self.receiver.clone());
         builder.write_block_with_len(match self.init.clone() {
@@ -94,6 +108,7 @@ impl AsBlock for Message {
         builder.write_u64(self.opcode);
         builder.write_block_with_len(self.body.clone());
         builder.write_u64(self.timestamp);
+        builder.write_u64(self.gas_limit);
         builder.build()
     }
 }
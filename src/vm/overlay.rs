@@ -0,0 +1,72 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use super::{block::Block, env::{Repository, TransactionPart}};
+
+// Слой поверх Repository на время одной транзакции.
+// Каждое следующее сообщение видит результаты предыдущих, а в Repository всё записывается один раз в конце
+pub struct Overlay {
+    repository: Rc<RefCell<dyn Repository>>,
+    programs: HashMap<String, Block>,
+    data: HashMap<String, Block>,
+    // Адрес -> ключ -> значение, None - ключ удалён в этой транзакции
+    storage: HashMap<String, HashMap<String, Option<Block>>>,
+}
+
+impl Overlay {
+    pub fn new(repository: Rc<RefCell<dyn Repository>>) -> Self {
+        Self {
+            repository,
+            programs: HashMap::new(),
+            data: HashMap::new(),
+            storage: HashMap::new(),
+        }
+    }
+
+    pub fn get_contract_program(&self, address: Block) -> Option<Block> {
+        match self.programs.get(&address.to_string()) {
+            Some(program) => Some(program.clone()),
+            None => self.repository.borrow().get_contract_program(address),
+        }
+    }
+
+    pub fn get_contract_data(&self, address: Block) -> Option<Block> {
+        match self.data.get(&address.to_string()) {
+            Some(data) => Some(data.clone()),
+            None => self.repository.borrow().get_contract_data(address),
+        }
+    }
+
+    pub fn get_contract_storage(&self, address: Block, key: Block) -> Option<Block> {
+        match self.storage.get(&address.to_string()).and_then(|storage| storage.get(&key.to_string())) {
+            Some(value) => value.clone(),
+            None => self.repository.borrow().get_contract_storage(address, key),
+        }
+    }
+
+    pub fn set_contract_program(&mut self, address: Block, program: Block) {
+        self.programs.insert(address.to_string(), program);
+    }
+
+    pub fn set_contract_data(&mut self, address: Block, data: Block) {
+        self.data.insert(address.to_string(), data);
+    }
+
+    pub fn set_contract_storage(&mut self, address: Block, key: Block, value: Option<Block>) {
+        self.storage.entry(address.to_string()).or_default().insert(key.to_string(), value);
+    }
+
+    // Состояние контрактов берётся из слоя, а не из частей транзакции:
+    // сообщения выполняются не в порядке их timestamp, и последнее по времени не обязательно последнее по исполнению
+    pub fn flush(&mut self, transaction: TransactionPart) {
+        let mut repository = self.repository.borrow_mut();
+        repository.save_transaction(transaction);
+        for (address, data) in self.data.drain() {
+            let program = self.programs.remove(&address);
+            if let Some(address) = Block::from_string(address) {
+                repository.save_contract(address, program, data);
+            }
+        }
+        self.programs.clear();
+        self.storage.clear();
+    }
+}
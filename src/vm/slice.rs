@@ -1,9 +1,11 @@
-use super::{block::Block, utils::{get_u64, get_u8}};
+use super::{block::Block, uint::U256, utils::{get_u16, get_u32, get_u64, get_u8}};
 
 #[derive(Clone)]
 pub struct Slice {
     code: Vec<u8>,
     pointer: usize,
+    // Сколько байт было прочитано до того, как слайс восстановили из сериализованного вида
+    base: usize,
 }
 
 impl Slice {
@@ -11,13 +13,60 @@ impl Slice {
         Self {
             code: block.unpack(),
             pointer: 0,
+            base: 0,
         }
     }
 
+    // Слайс, у которого уже прочитано position байт и осталось remaining
+    pub fn from_position(remaining: Block, position: usize) -> Self {
+        Self {
+            code: remaining.unpack(),
+            pointer: 0,
+            base: position,
+        }
+    }
+
+    fn look(&self, length: usize) -> Option<&[u8]> {
+        self.code.get(self.pointer .. self.pointer.checked_add(length)?)
+    }
+
     fn get(&mut self, length: usize) -> Option<&[u8]> {
-        let slice = self.code.get(self.pointer .. (self.pointer + length))?;
+        let start = self.pointer;
+        self.look(length)?;
         self.pointer += length;
-        Some(slice)
+        Some(&self.code[start..self.pointer])
+    }
+
+    pub fn skip(&mut self, length: usize) -> Option<()> {
+        self.get(length)?;
+        Some(())
+    }
+
+    // peek_* читают так же, как read_*, но не сдвигают позицию
+    pub fn peek_u8(&self) -> Option<u8> {
+        get_u8(self.look(size_of::<u8>())?)
+    }
+
+    pub fn peek_u16(&self) -> Option<u16> {
+        get_u16(self.look(size_of::<u16>())?)
+    }
+
+    pub fn peek_u32(&self) -> Option<u32> {
+        get_u32(self.look(size_of::<u32>())?)
+    }
+
+    pub fn peek_u64(&self) -> Option<u64> {
+        get_u64(self.look(size_of::<u64>())?)
+    }
+
+    pub fn peek_block(&self, length: usize) -> Option<Block> {
+        Some(Block::new(self.look(length)?))
+    }
+
+    pub fn read_rest(&mut self) -> Block {
+        let block = Block::new(self.remaining());
+        self.pointer = self.code.len();
+        block
     }
 
     pub fn read_u64(&mut self) -> Option<u64> {
@@ -28,6 +77,18 @@ impl Slice {
         get_u8(self.get(size_of::<u8>())?)
     }
 
+    pub fn read_u16(&mut self) -> Option<u16> {
+        get_u16(self.get(size_of::<u16>())?)
+    }
+
+    pub fn read_u32(&mut self) -> Option<u32> {
+        get_u32(self.get(size_of::<u32>())?)
+    }
+
+    pub fn read_u256(&mut self) -> Option<U256> {
+        U256::from_be_slice(self.get(U256::BYTES)?)
+    }
+
     pub fn read_block(&mut self, length: usize) -> Option<Block> {
         Some(Block::new(self.get(length)?))
     }
@@ -40,5 +101,13 @@ impl Slice {
     pub fn len(&self) -> usize {
         return self.code.len() - self.pointer
     }
+
+    pub fn position(&self) -> usize {
+        self.base + self.pointer
+    }
+
+    pub fn remaining(&self) -> &[u8] {
+        &self.code[self.pointer..]
+    }
 }
 
@@ -37,10 +37,59 @@ impl<T: Clone> Stack<T> {
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
     pub fn pop(&mut self) -> Option<T> {
         self.data.pop()
     }
 
+    // Убирает элемент на глубине offset, остальные сдвигаются
+    pub fn remove(&mut self, offset: usize) -> Option<T> {
+        let len = self.data.len();
+        if offset < len {
+            Some(self.data.remove(len - 1 - offset))
+        } else {
+            None
+        }
+    }
+
+    // Вставляет элемент так, чтобы он оказался на глубине offset
+    pub fn insert(&mut self, offset: usize, value: T) -> bool {
+        let len = self.data.len();
+        if offset <= len {
+            self.data.insert(len - offset, value);
+            true
+        } else {
+            false
+        }
+    }
+
+    // Доступ по индексу от дна стека
+    pub fn get_at(&self, index: usize) -> Option<&T> {
+        self.data.get(index)
+    }
+
+    pub fn get_mut_at(&mut self, index: usize) -> Option<&mut T> {
+        self.data.get_mut(index)
+    }
+
+    // Убирает length элементов начиная с индекса index от дна, элементы выше сдвигаются вниз
+    pub fn remove_at(&mut self, index: usize, length: usize) -> bool {
+        match index.checked_add(length) {
+            Some(end) if end <= self.data.len() => {
+                self.data.drain(index..end);
+                true
+            },
+            _ => false,
+        }
+    }
+
     pub fn change(&mut self, first: usize, second: usize) {
         let len = self.data.len();
         if first < len && second < len {
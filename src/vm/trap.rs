@@ -0,0 +1,137 @@
+use super::{block::{AsBlock, Block}, builder::Builder};
+
+// Причина аварийной остановки VM
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Trap {
+    StackUnderflow,
+    TypeError,
+    BadJump,
+    TruncatedOperand,
+    UnknownOpcode,
+    DivisionByZero,
+    SliceUnderflow,
+    OutOfGas,
+    SendLimit,
+    DepthLimit,
+    MessageLimit,
+    Overflow,
+    BadProgram,
+    BadLocal,
+    MemoryLimit,
+    NotFound,
+    BadEncoding,
+    SliceNotEmpty,
+    SizeLimit,
+    ValueLimit,
+    Throw(u64),
+}
+
+impl Trap {
+    // Нехватку газа перехватить нельзя, иначе обработчик сможет продолжить выполнение
+    pub fn is_catchable(&self) -> bool {
+        *self != Trap::OutOfGas
+    }
+
+    // Коды THROW пересекаются с кодами ошибок VM, поэтому обработчик отличает их по этому признаку
+    pub fn is_thrown(&self) -> bool {
+        matches!(self, Trap::Throw(_))
+    }
+
+    pub fn code(&self) -> u64 {
+        match self {
+            Trap::StackUnderflow => 1,
+            Trap::TypeError => 2,
+            Trap::BadJump => 3,
+            Trap::TruncatedOperand => 4,
+            Trap::UnknownOpcode => 5,
+            Trap::DivisionByZero => 6,
+            Trap::SliceUnderflow => 7,
+            Trap::OutOfGas => 8,
+            Trap::SendLimit => 9,
+            Trap::DepthLimit => 10,
+            Trap::MessageLimit => 11,
+            Trap::Overflow => 12,
+            Trap::BadProgram => 13,
+            Trap::BadLocal => 14,
+            Trap::MemoryLimit => 15,
+            Trap::NotFound => 16,
+            Trap::BadEncoding => 17,
+            Trap::SliceNotEmpty => 18,
+            Trap::SizeLimit => 19,
+            Trap::ValueLimit => 20,
+            Trap::Throw(code) => *code,
+        }
+    }
+}
+
+impl ToString for Trap {
+    fn to_string(&self) -> String {
+        match self {
+            Trap::StackUnderflow => "stack underflow",
+            Trap::TypeError => "type error",
+            Trap::BadJump => "bad jump target",
+            Trap::TruncatedOperand => "truncated operand",
+            Trap::UnknownOpcode => "unknown opcode",
+            Trap::DivisionByZero => "division by zero",
+            Trap::SliceUnderflow => "slice underflow",
+            Trap::OutOfGas => "out of gas",
+            Trap::SendLimit => "too many sends",
+            Trap::DepthLimit => "message cascade too deep",
+            Trap::MessageLimit => "too many messages in transaction",
+            Trap::Overflow => "integer overflow",
+            Trap::BadProgram => "program failed verification",
+            Trap::BadLocal => "local variable outside of frame",
+            Trap::MemoryLimit => "scratch memory limit exceeded",
+            Trap::NotFound => "tuple index or map key not found",
+            Trap::BadEncoding => "malformed value encoding",
+            Trap::SliceNotEmpty => "slice has unread bytes",
+            Trap::SizeLimit => "block size limit exceeded",
+            Trap::ValueLimit => "tuple or map too large or nested too deep",
+            Trap::Throw(_) => "thrown",
+        }.to_string()
+    }
+}
+
+// Результат выполнения VM::run
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExitCode {
+    Success,
+    Trap(Trap),
+}
+
+impl ExitCode {
+    pub fn is_success(&self) -> bool {
+        *self == ExitCode::Success
+    }
+}
+
+impl ToString for ExitCode {
+    fn to_string(&self) -> String {
+        match self {
+            ExitCode::Success => "success".to_string(),
+            ExitCode::Trap(Trap::Throw(code)) => format!("thrown {}", code),
+            ExitCode::Trap(trap) => format!("trap {} ({})", trap.code(), trap.to_string()),
+        }
+    }
+}
+
+impl AsBlock for ExitCode {
+    fn get_as_block(&self) -> Block {
+        let mut builder = Builder::new();
+        match self {
+            ExitCode::Success => {
+                builder.write_u8(0);
+                builder.write_u64(0);
+            },
+            ExitCode::Trap(Trap::Throw(code)) => {
+                builder.write_u8(2);
+                builder.write_u64(*code);
+            },
+            ExitCode::Trap(trap) => {
+                builder.write_u8(1);
+                builder.write_u64(trap.code());
+            },
+        }
+        builder.build()
+    }
+}
@@ -0,0 +1,410 @@
+use std::{cmp::Ordering, ops::{BitAnd, BitOr, BitXor, Not, Shl, Shr}};
+
+// Беззнаковое 256-битное число, limbs хранятся от младшего к старшему
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    pub const ZERO: U256 = U256([0; 4]);
+    pub const ONE: U256 = U256([1, 0, 0, 0]);
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+    pub const BITS: u32 = 256;
+    pub const BYTES: usize = 32;
+
+    pub fn from_u64(value: u64) -> Self {
+        U256([value, 0, 0, 0])
+    }
+
+    pub fn to_u64(self) -> Option<u64> {
+        if self.0[1..].iter().all(|x| *x == 0) {
+            Some(self.0[0])
+        } else {
+            None
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Self::ZERO
+    }
+
+    pub fn from_be_bytes(bytes: [u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let start = (3 - i) * 8;
+            *limb = u64::from_be_bytes(bytes[start..start + 8].try_into().unwrap());
+        }
+        U256(limbs)
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            let start = (3 - i) * 8;
+            bytes[start..start + 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    // Big-endian байты произвольной длины до 32
+    pub fn from_be_slice(slice: &[u8]) -> Option<Self> {
+        if slice.len() > Self::BYTES {
+            return None;
+        }
+        let mut bytes = [0u8; 32];
+        bytes[Self::BYTES - slice.len()..].copy_from_slice(slice);
+        Some(Self::from_be_bytes(bytes))
+    }
+
+    pub fn from_dec_str(value: &str) -> Option<Self> {
+        if value.is_empty() {
+            return None;
+        }
+        let mut result = Self::ZERO;
+        for digit in value.chars() {
+            let digit = digit.to_digit(10)? as u64;
+            result = result.checked_mul(Self::from_u64(10))?.checked_add(Self::from_u64(digit))?;
+        }
+        Some(result)
+    }
+
+    pub fn overflowing_add(self, other: Self) -> (Self, bool) {
+        let mut result = [0u64; 4];
+        let mut carry = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (sum, first) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, second) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = first || second;
+        }
+        (U256(result), carry)
+    }
+
+    pub fn overflowing_sub(self, other: Self) -> (Self, bool) {
+        let mut result = [0u64; 4];
+        let mut borrow = false;
+        for (i, limb) in result.iter_mut().enumerate() {
+            let (difference, first) = self.0[i].overflowing_sub(other.0[i]);
+            let (difference, second) = difference.overflowing_sub(borrow as u64);
+            *limb = difference;
+            borrow = first || second;
+        }
+        (U256(result), borrow)
+    }
+
+    pub fn overflowing_mul(self, other: Self) -> (Self, bool) {
+        let mut result = [0u64; 8];
+        for i in 0..4 {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let product = self.0[i] as u128 * other.0[j] as u128 + result[i + j] as u128 + carry;
+                result[i + j] = product as u64;
+                carry = product >> 64;
+            }
+            result[i + 4] = carry as u64;
+        }
+        let overflow = result[4..].iter().any(|x| *x != 0);
+        (U256(result[..4].try_into().unwrap()), overflow)
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        match self.overflowing_add(other) {
+            (value, false) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        match self.overflowing_sub(other) {
+            (value, false) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        match self.overflowing_mul(other) {
+            (value, false) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn wrapping_add(self, other: Self) -> Self {
+        self.overflowing_add(other).0
+    }
+
+    pub fn wrapping_sub(self, other: Self) -> Self {
+        self.overflowing_sub(other).0
+    }
+
+    pub fn wrapping_mul(self, other: Self) -> Self {
+        self.overflowing_mul(other).0
+    }
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        self.checked_add(other).unwrap_or(Self::MAX)
+    }
+
+    pub fn saturating_sub(self, other: Self) -> Self {
+        self.checked_sub(other).unwrap_or(Self::ZERO)
+    }
+
+    pub fn saturating_mul(self, other: Self) -> Self {
+        self.checked_mul(other).unwrap_or(Self::MAX)
+    }
+
+    // Деление столбиком по одному биту
+    pub fn checked_div_rem(self, other: Self) -> Option<(Self, Self)> {
+        if other.is_zero() {
+            return None;
+        }
+        let mut quotient = Self::ZERO;
+        let mut remainder = Self::ZERO;
+        for bit in (0..Self::BITS).rev() {
+            remainder = remainder << 1;
+            if self.bit(bit) {
+                remainder.0[0] |= 1;
+            }
+            if remainder >= other {
+                remainder = remainder.wrapping_sub(other);
+                quotient.0[(bit / 64) as usize] |= 1 << (bit % 64);
+            }
+        }
+        Some((quotient, remainder))
+    }
+
+    pub fn checked_div(self, other: Self) -> Option<Self> {
+        Some(self.checked_div_rem(other)?.0)
+    }
+
+    pub fn checked_rem(self, other: Self) -> Option<Self> {
+        Some(self.checked_div_rem(other)?.1)
+    }
+
+    pub fn bit(&self, index: u32) -> bool {
+        self.0[(index / 64) as usize] & (1 << (index % 64)) != 0
+    }
+
+    fn div_rem_small(self, divisor: u64) -> (Self, u64) {
+        let mut result = [0u64; 4];
+        let mut remainder = 0u128;
+        for i in (0..4).rev() {
+            let current = (remainder << 64) | self.0[i] as u128;
+            result[i] = (current / divisor as u128) as u64;
+            remainder = current % divisor as u128;
+        }
+        (U256(result), remainder as u64)
+    }
+}
+
+impl BitAnd for U256 {
+    type Output = U256;
+
+    fn bitand(self, other: Self) -> Self {
+        U256([self.0[0] & other.0[0], self.0[1] & other.0[1], self.0[2] & other.0[2], self.0[3] & other.0[3]])
+    }
+}
+
+impl BitOr for U256 {
+    type Output = U256;
+
+    fn bitor(self, other: Self) -> Self {
+        U256([self.0[0] | other.0[0], self.0[1] | other.0[1], self.0[2] | other.0[2], self.0[3] | other.0[3]])
+    }
+}
+
+impl BitXor for U256 {
+    type Output = U256;
+
+    fn bitxor(self, other: Self) -> Self {
+        U256([self.0[0] ^ other.0[0], self.0[1] ^ other.0[1], self.0[2] ^ other.0[2], self.0[3] ^ other.0[3]])
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> Self {
+        U256([!self.0[0], !self.0[1], !self.0[2], !self.0[3]])
+    }
+}
+
+// Сдвиг на 256 бит и больше даёт ноль
+impl Shl<u64> for U256 {
+    type Output = U256;
+
+    fn shl(self, shift: u64) -> Self {
+        if shift >= Self::BITS as u64 {
+            return Self::ZERO;
+        }
+        let limbs = (shift / 64) as usize;
+        let bits = shift % 64;
+        let mut result = [0u64; 4];
+        for (i, limb) in result.iter_mut().enumerate().skip(limbs) {
+            *limb = self.0[i - limbs] << bits;
+            if bits > 0 && i > limbs {
+                *limb |= self.0[i - limbs - 1] >> (64 - bits);
+            }
+        }
+        U256(result)
+    }
+}
+
+impl Shr<u64> for U256 {
+    type Output = U256;
+
+    fn shr(self, shift: u64) -> Self {
+        if shift >= Self::BITS as u64 {
+            return Self::ZERO;
+        }
+        let limbs = (shift / 64) as usize;
+        let bits = shift % 64;
+        let mut result = [0u64; 4];
+        for (i, limb) in result.iter_mut().take(4 - limbs).enumerate() {
+            *limb = self.0[i + limbs] >> bits;
+            if bits > 0 && i + limbs + 1 < 4 {
+                *limb |= self.0[i + limbs + 1] << (64 - bits);
+            }
+        }
+        U256(result)
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        for i in (0..4).rev() {
+            match self.0[i].cmp(&other.0[i]) {
+                Ordering::Equal => continue,
+                ordering => return ordering,
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl ToString for U256 {
+    fn to_string(&self) -> String {
+        if self.is_zero() {
+            return "0".to_string();
+        }
+        let mut digits = Vec::new();
+        let mut value = *self;
+        while !value.is_zero() {
+            let (quotient, digit) = value.div_rem_small(10);
+            digits.push(b'0' + digit as u8);
+            value = quotient;
+        }
+        digits.reverse();
+        String::from_utf8(digits).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::U256;
+
+    fn hex(value: &str) -> U256 {
+        U256::from_be_slice(&hex::decode(value).unwrap()).unwrap()
+    }
+
+    fn pow2(exponent: u64) -> U256 {
+        U256::ONE << exponent
+    }
+
+    #[test]
+    fn add_carries_across_limbs() {
+        assert_eq!(U256::from_u64(u64::MAX).checked_add(U256::ONE), Some(pow2(64)));
+        assert_eq!(pow2(192).wrapping_sub(U256::ONE).checked_add(U256::ONE), Some(pow2(192)));
+        assert_eq!(U256::MAX.checked_add(U256::ONE), None);
+        assert_eq!(U256::MAX.overflowing_add(U256::from_u64(2)), (U256::ONE, true));
+        assert_eq!(U256::MAX.saturating_add(U256::ONE), U256::MAX);
+    }
+
+    #[test]
+    fn sub_borrows_across_limbs() {
+        assert_eq!(pow2(64).checked_sub(U256::ONE), Some(U256::from_u64(u64::MAX)));
+        assert_eq!(pow2(255).checked_sub(U256::ONE), Some(U256::MAX >> 1));
+        assert_eq!(U256::ZERO.checked_sub(U256::ONE), None);
+        assert_eq!(U256::ZERO.wrapping_sub(U256::ONE), U256::MAX);
+        assert_eq!(U256::ONE.saturating_sub(U256::from_u64(2)), U256::ZERO);
+    }
+
+    #[test]
+    fn mul_carries_and_overflows() {
+        let max = U256::from_u64(u64::MAX);
+        assert_eq!(max.checked_mul(max), Some(hex("fffffffffffffffe0000000000000001")));
+        assert_eq!(pow2(128).checked_mul(pow2(127)), Some(pow2(255)));
+        assert_eq!(pow2(128).checked_mul(pow2(128)), None);
+        assert_eq!(U256::MAX.wrapping_mul(U256::MAX), U256::ONE);
+        assert_eq!(U256::MAX.saturating_mul(U256::from_u64(2)), U256::MAX);
+    }
+
+    #[test]
+    fn div_rem_by_zero_and_small() {
+        assert_eq!(U256::ONE.checked_div_rem(U256::ZERO), None);
+        assert_eq!(U256::from_u64(17).checked_div_rem(U256::from_u64(5)), Some((U256::from_u64(3), U256::from_u64(2))));
+        assert_eq!(pow2(200).checked_div(pow2(100)), Some(pow2(100)));
+        assert_eq!(U256::MAX.checked_rem(U256::from_u64(10)), Some(U256::from_u64(5)));
+    }
+
+    #[test]
+    fn div_rem_with_divisor_at_least_2_pow_255() {
+        let divisor = pow2(255).checked_add(U256::ONE).unwrap();
+        let remainder = U256::MAX.wrapping_sub(divisor);
+        assert_eq!(U256::MAX.checked_div_rem(divisor), Some((U256::ONE, remainder)));
+        assert_eq!(U256::MAX.checked_div_rem(pow2(255)), Some((U256::ONE, U256::MAX >> 1)));
+        assert_eq!(U256::MAX.checked_div_rem(U256::MAX), Some((U256::ONE, U256::ZERO)));
+        assert_eq!(pow2(255).checked_div_rem(divisor), Some((U256::ZERO, pow2(255))));
+    }
+
+    #[test]
+    fn shifts() {
+        let value = hex("0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef");
+        assert_eq!(value << 0, value);
+        assert_eq!(value >> 0, value);
+        assert_eq!(value << 64, hex("0123456789abcdef0123456789abcdef0123456789abcdef0000000000000000"));
+        assert_eq!(value >> 64, hex("0123456789abcdef0123456789abcdef0123456789abcdef"));
+        assert_eq!(value << 4, hex("123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef0"));
+        assert_eq!(value >> 4, hex("00123456789abcdef0123456789abcdef0123456789abcdef0123456789abcde"));
+        assert_eq!(U256::ONE << 255, pow2(255));
+        assert_eq!(U256::MAX >> 255, U256::ONE);
+        assert_eq!(U256::MAX << 256, U256::ZERO);
+        assert_eq!(U256::MAX >> 256, U256::ZERO);
+        assert_eq!(U256::MAX << u64::MAX, U256::ZERO);
+    }
+
+    #[test]
+    fn decimal_parsing() {
+        assert_eq!(U256::from_dec_str("0"), Some(U256::ZERO));
+        assert_eq!(U256::from_dec_str("18446744073709551616"), Some(pow2(64)));
+        let max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        assert_eq!(U256::from_dec_str(max), Some(U256::MAX));
+        assert_eq!(U256::from_dec_str("115792089237316195423570985008687907853269984665640564039457584007913129639936"), None);
+        assert_eq!(U256::from_dec_str(&format!("{}0", max)), None);
+        assert_eq!(U256::from_dec_str(""), None);
+        assert_eq!(U256::from_dec_str("12a"), None);
+        assert_eq!(U256::from_dec_str("-1"), None);
+    }
+
+    #[test]
+    fn to_string_round_trip() {
+        for value in [U256::ZERO, U256::ONE, pow2(64), pow2(255), U256::MAX, hex("0123456789abcdef0123456789abcdef")] {
+            assert_eq!(U256::from_dec_str(&value.to_string()), Some(value));
+        }
+        assert_eq!(pow2(64).to_string(), "18446744073709551616");
+        assert_eq!(U256::MAX.to_string(), "115792089237316195423570985008687907853269984665640564039457584007913129639935");
+    }
+
+    #[test]
+    fn byte_conversions() {
+        let value = hex("0102");
+        assert_eq!(value.to_u64(), Some(0x0102));
+        assert_eq!(pow2(64).to_u64(), None);
+        assert_eq!(U256::from_be_bytes(value.to_be_bytes()), value);
+        assert_eq!(U256::from_be_slice(&[0; 33]), None);
+    }
+}
@@ -1,17 +1,68 @@
 use std::{u16, u8};
 
-use super::Value;
+use super::{trap::Trap, uint::U256, Value};
 
-pub fn operate<F>(pair: Option<(&Value, &Value)>, f: F) -> Option<u64>
+pub fn operate<F>(pair: Option<(&Value, &Value)>, f: F) -> Result<u64, Trap>
     where F: Fn(u64, u64) -> u64 {
-    if let Some(pair) = pair {
-        if let Value::Number(first) = pair.0  {
-            if let Value::Number(second) = pair.1 {
-                return Some(f(first.clone(), second.clone()));
-            }
-        }
+    try_operate(pair, |a, b| Ok(f(a, b)))
+}
+
+// Как operate, но сама операция может завершиться ловушкой
+pub fn try_operate<F>(pair: Option<(&Value, &Value)>, f: F) -> Result<u64, Trap>
+    where F: Fn(u64, u64) -> Result<u64, Trap> {
+    match pair {
+        Some((Value::Number(first), Value::Number(second))) => f(*first, *second),
+        Some(_) => Err(Trap::TypeError),
+        None => Err(Trap::StackUnderflow),
+    }
+}
+
+// Числа u64 расширяются до U256
+pub fn operate_wide<T, F>(pair: Option<(&Value, &Value)>, f: F) -> Result<T, Trap>
+    where F: Fn(U256, U256) -> Result<T, Trap> {
+    match pair {
+        Some((first, second)) => f(get_wide(first)?, get_wide(second)?),
+        None => Err(Trap::StackUnderflow),
+    }
+}
+
+fn get_wide(value: &Value) -> Result<U256, Trap> {
+    match value {
+        Value::Number(number) => Ok(U256::from_u64(*number)),
+        Value::Wide(number) => Ok(*number),
+        _ => Err(Trap::TypeError),
     }
-    None
+}
+
+pub fn shift_left(value: u64, shift: u64) -> u64 {
+    if shift >= u64::BITS as u64 {
+        0
+    } else {
+        value << shift
+    }
+}
+
+pub fn shift_right(value: u64, shift: u64) -> u64 {
+    if shift >= u64::BITS as u64 {
+        0
+    } else {
+        value >> shift
+    }
+}
+
+// Знаковые операции работают с u64 как с i64 в дополнительном коде
+pub fn signed_div(first: u64, second: u64) -> Result<u64, Trap> {
+    if second == 0 {
+        return Err(Trap::DivisionByZero);
+    }
+    Ok((first as i64).checked_div(second as i64).ok_or(Trap::Overflow)? as u64)
+}
+
+pub fn signed_rem(first: u64, second: u64) -> Result<u64, Trap> {
+    if second == 0 {
+        return Err(Trap::DivisionByZero);
+    }
+    Ok((first as i64).checked_rem(second as i64).ok_or(Trap::Overflow)? as u64)
 }
 
 pub fn cond_sign(value: bool) -> u64 {
@@ -30,14 +81,15 @@ pub fn get_u16(slice: &[u8]) -> Option<u16> {
     Some(u16::from_be_bytes(slice.try_into().ok()?))
 }
 
-// pub fn get_u32(slice: &[u8]) -> Option<u32> {
-//     Some(u32::from_be_bytes(slice.try_into().ok()?))
-// }
+pub fn get_u32(slice: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(slice.try_into().ok()?))
+}
 
 pub fn get_u64(slice: &[u8]) -> Option<u64> {
     Some(u64::from_be_bytes(slice.try_into().ok()?))
 }
 
+#[derive(Clone, Copy)]
 pub enum Direction {
     Forward,
     Backward,
@@ -50,3 +102,14 @@ pub fn get_relative_reference(reference: u16) -> (Direction, u16) {
         (Direction::Forward, reference)
     }
 }
+
+// Обратное к get_relative_reference, старший бит задаёт направление
+pub fn make_relative_reference(direction: Direction, offset: u16) -> Option<u16> {
+    if offset & (0b1 << 15) != 0 {
+        return None;
+    }
+    match direction {
+        Direction::Forward => Some(offset),
+        Direction::Backward => Some(offset | (0b1 << 15)),
+    }
+}